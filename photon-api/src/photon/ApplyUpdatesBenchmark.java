@@ -0,0 +1,83 @@
+package photon;
+
+import java.nio.ByteBuffer;
+import java.nio.charset.StandardCharsets;
+
+/**
+ * Compares applying the same batch passed as a String and as a direct ByteBuffer.
+ * Runs embedded, without a server: {@code java photon.ApplyUpdatesBenchmark [text nodes]}.
+ */
+public class ApplyUpdatesBenchmark {
+    private static final int WARMUP_RUNS = 5;
+    private static final int RUNS = 20;
+
+    public static void main(String[] args) throws InterruptedException {
+        int size = args.length > 0 ? Integer.parseInt(args[0]) : 20_000;
+        String batch = batch(size);
+        byte[] bytes = batch.getBytes(StandardCharsets.UTF_8);
+        ByteBuffer buffer = ByteBuffer.allocateDirect(bytes.length);
+        buffer.put(bytes);
+
+        PhotonApi api = new PhotonApi();
+        long handle = api.startEmbedded();
+        try {
+            // make sure the instance is running before timing anything
+            waitUntilApplied(api, handle, nodes(size));
+            System.out.printf("batch of %d text nodes, %d bytes%n", size, bytes.length);
+            System.out.printf("String:     %.2f ms per batch%n", time(() -> api.applyUpdates(handle, batch)));
+            System.out.printf("ByteBuffer: %.2f ms per batch%n", time(() -> {
+                buffer.rewind();
+                api.applyUpdates(handle, buffer);
+            }));
+        } finally {
+            api.stop(handle);
+            api.waitForExit(handle);
+        }
+    }
+
+    /** Creates the root and the text nodes that {@link #batch} updates. */
+    private static String nodes(int size) {
+        StringBuilder json = new StringBuilder("[{\"update-type\":\"make-node\",\"node\":0,\"type\":\"root\"}");
+        for (int node = 1; node <= size; node++) {
+            json.append(",{\"update-type\":\"make-node\",\"node\":").append(node).append(",\"type\":\"text\"}");
+        }
+        return json.append("]").toString();
+    }
+
+    /** Sets the same attributes of existing nodes every time, so each run does the same amount of work. */
+    private static String batch(int size) {
+        StringBuilder json = new StringBuilder("[[1]");
+        for (int node = 1; node <= size; node++) {
+            json.append(",{\"update-type\":\"set-attr\",\"node\":").append(node)
+                .append(",\"attr\":\"text\",\"value\":\"line number ").append(node).append("\"}")
+                .append(",{\"update-type\":\"set-attr\",\"node\":").append(node)
+                .append(",\"attr\":\"origin\",\"value\":{\"x\":0.5,\"y\":").append(node * 16).append("}}");
+        }
+        return json.append("]").toString();
+    }
+
+    private static void waitUntilApplied(PhotonApi api, long handle, String batch) throws InterruptedException {
+        while (true) {
+            try {
+                api.applyUpdates(handle, batch);
+                return;
+            } catch (PhotonException e) {
+                if (!api.isRunning(handle)) {
+                    throw e;
+                }
+                Thread.sleep(10);
+            }
+        }
+    }
+
+    private static double time(Runnable run) {
+        for (int i = 0; i < WARMUP_RUNS; i++) {
+            run.run();
+        }
+        long start = System.nanoTime();
+        for (int i = 0; i < RUNS; i++) {
+            run.run();
+        }
+        return (System.nanoTime() - start) / 1e6 / RUNS;
+    }
+}
@@ -0,0 +1,11 @@
+package photon;
+
+public interface CallbackListener {
+    /**
+     * Called on the photon event loop thread.
+     *
+     * @param key       callback attribute, e.g. "on-click" or "on-wheel"
+     * @param arguments point of the click or wheel delta
+     */
+    void onCallback(long node, String key, long logId, float[] arguments);
+}
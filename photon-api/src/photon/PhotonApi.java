@@ -1,11 +1,55 @@
 package photon;
 
+import java.nio.ByteBuffer;
+
+/**
+ * Native methods throw {@link PhotonException} instead of crashing the JVM when photon fails.
+ */
 public class PhotonApi {
     static {
         System.loadLibrary("photonapi");
     }
 
-    public native void run(int port);
-    public native void applyUpdates(String updates);
-    public native float measureText(String text);
+    /**
+     * Delivers node callbacks to {@code listener} instead of the server. Takes effect on the next {@link #start}.
+     */
+    public native void setCallbackListener(CallbackListener listener);
+    /**
+     * Opens a window connected to the server on {@code port} without waiting for it to close.
+     *
+     * @return handle of the window, valid until {@link #waitForExit} returns
+     */
+    public native long start(int port);
+    /**
+     * Opens a window that isn't connected to any server and only shows updates pushed with {@link #applyUpdates}.
+     *
+     * @return handle of the window, valid until {@link #waitForExit} returns
+     */
+    public native long startEmbedded();
+    /**
+     * Asks the window to close and returns immediately.
+     */
+    public native void stop(long handle);
+    public native boolean isRunning(long handle);
+    /**
+     * Blocks until the window is closed and its connection and renderer are torn down, then releases the handle.
+     */
+    public native void waitForExit(long handle);
+    public native void applyUpdates(long handle, String updates);
+    /**
+     * Applies a UTF-8 JSON batch between position and limit of a direct buffer without copying it,
+     * which is cheaper than {@link #applyUpdates(long, String)} for large batches. Consumes the remaining bytes.
+     */
+    public void applyUpdates(long handle, ByteBuffer updates) {
+        if (!updates.isDirect()) {
+            throw new IllegalArgumentException("updates must be a direct ByteBuffer");
+        }
+        applyUpdatesBuffer(handle, updates, updates.position(), updates.remaining());
+        updates.position(updates.limit());
+    }
+    private native void applyUpdatesBuffer(long handle, ByteBuffer updates, int offset, int length);
+    /**
+     * @param font path to a font file, or null for the default font built into photon
+     */
+    public native TextMetrics measureText(String text, String font, float size);
 }
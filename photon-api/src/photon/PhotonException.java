@@ -0,0 +1,25 @@
+package photon;
+
+/**
+ * Thrown by {@link PhotonApi} methods when the native side fails or panics.
+ */
+public class PhotonException extends RuntimeException {
+    private final String nativeBacktrace;
+
+    public PhotonException(String message, String nativeBacktrace) {
+        super(message);
+        this.nativeBacktrace = nativeBacktrace;
+    }
+
+    /**
+     * @return backtrace of the Rust code that failed, or an empty string if it is not known
+     */
+    public String getNativeBacktrace() {
+        return nativeBacktrace;
+    }
+
+    @Override
+    public String toString() {
+        return nativeBacktrace.isEmpty() ? super.toString() : super.toString() + "\n" + nativeBacktrace;
+    }
+}
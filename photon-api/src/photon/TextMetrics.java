@@ -0,0 +1,22 @@
+package photon;
+
+public final class TextMetrics {
+    public final float width;
+    public final float height;
+    /** Distance from the top of the line to the baseline. */
+    public final float ascent;
+    /** Distance from the baseline to the bottom of the line. */
+    public final float descent;
+
+    public TextMetrics(float width, float height, float ascent, float descent) {
+        this.width = width;
+        this.height = height;
+        this.ascent = ascent;
+        this.descent = descent;
+    }
+
+    @Override
+    public String toString() {
+        return "TextMetrics{width=" + width + ", height=" + height + ", ascent=" + ascent + ", descent=" + descent + "}";
+    }
+}
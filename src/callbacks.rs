@@ -0,0 +1,23 @@
+use serde::Serialize;
+
+use crate::connection::Outgoing;
+
+#[derive(Debug, Serialize)]
+pub struct CallbackMessage<'a> {
+    pub log_id: u64,
+    pub ts: u64,
+    pub node: u64,
+    pub key: &'a str,
+    pub arguments: Vec<f32>,
+}
+
+/// Where the `Controller` delivers callbacks fired by user input.
+pub trait CallbackSink: Send + Sync {
+    fn send_callback(&self, msg: &CallbackMessage);
+}
+
+impl CallbackSink for Outgoing {
+    fn send_callback(&self, msg: &CallbackMessage) {
+        self.send(msg);
+    }
+}
@@ -0,0 +1,119 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, BigEndian};
+use serde::Serialize;
+
+/// Single message on the wire: big-endian u32 payload length followed by the payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame(pub Vec<u8>);
+
+impl Frame {
+    pub fn json<T: Serialize>(value: &T) -> Frame {
+        Frame(serde_json::to_vec(value).expect("Can't serialize message"))
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+pub struct FrameReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R) -> Self {
+        FrameReader { inner }
+    }
+
+    /// Returns `Ok(None)` when the peer closed the stream between frames.
+    pub fn read_frame(&mut self) -> io::Result<Option<Frame>> {
+        let size = match self.inner.read_u32::<BigEndian>() {
+            Ok(size) => size,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut buf = vec![0u8; size as usize];
+        self.inner.read_exact(&mut buf)?;
+        Ok(Some(Frame(buf)))
+    }
+}
+
+pub struct FrameWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(inner: W) -> Self {
+        FrameWriter { inner }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        // header and payload go out in one write so nodelay sockets don't split them
+        let mut buf = Vec::with_capacity(4 + frame.0.len());
+        buf.write_u32::<BigEndian>(frame.0.len() as u32)?;
+        buf.extend_from_slice(&frame.0);
+        self.inner.write_all(&buf)?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+
+    fn local_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let (client, server) = local_pair();
+        let mut writer = FrameWriter::new(client);
+        let mut reader = FrameReader::new(server);
+        let frames = vec![Frame(b"first".to_vec()), Frame(vec![]), Frame(vec![7u8; 100_000])];
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        for frame in &frames {
+            assert_eq!(reader.read_frame().unwrap().as_ref(), Some(frame));
+        }
+    }
+
+    #[test]
+    fn test_burst_keeps_boundaries() {
+        let (client, server) = local_pair();
+        let handle = std::thread::spawn(move || {
+            let mut writer = FrameWriter::new(client);
+            for i in 0..1000u32 {
+                writer.write_frame(&Frame::json(&vec![i, i + 1])).unwrap();
+            }
+        });
+        let mut reader = FrameReader::new(server);
+        for i in 0..1000u32 {
+            let frame = reader.read_frame().unwrap().unwrap();
+            let value: Vec<u32> = serde_json::from_slice(frame.payload()).unwrap();
+            assert_eq!(value, vec![i, i + 1]);
+        }
+        handle.join().unwrap();
+        assert_eq!(reader.read_frame().unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncated_frame_is_error() {
+        let (mut client, server) = local_pair();
+        client.write_u32::<BigEndian>(10).unwrap();
+        client.write_all(b"short").unwrap();
+        drop(client);
+        let mut reader = FrameReader::new(server);
+        assert!(reader.read_frame().is_err());
+    }
+}
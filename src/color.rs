@@ -0,0 +1,121 @@
+use serde_json::Value;
+use webrender::api::{ColorF, ColorU};
+
+const COMPONENTS: [&str; 4] = ["r", "g", "b", "a"];
+
+const NAMED_COLORS: &[(&str, (u8, u8, u8, u8))] = &[
+    ("transparent", (0, 0, 0, 0)),
+    ("black", (0, 0, 0, 255)),
+    ("white", (255, 255, 255, 255)),
+    ("gray", (128, 128, 128, 255)),
+    ("grey", (128, 128, 128, 255)),
+    ("silver", (192, 192, 192, 255)),
+    ("red", (255, 0, 0, 255)),
+    ("maroon", (128, 0, 0, 255)),
+    ("orange", (255, 165, 0, 255)),
+    ("yellow", (255, 255, 0, 255)),
+    ("olive", (128, 128, 0, 255)),
+    ("lime", (0, 255, 0, 255)),
+    ("green", (0, 128, 0, 255)),
+    ("cyan", (0, 255, 255, 255)),
+    ("aqua", (0, 255, 255, 255)),
+    ("teal", (0, 128, 128, 255)),
+    ("blue", (0, 0, 255, 255)),
+    ("navy", (0, 0, 128, 255)),
+    ("magenta", (255, 0, 255, 255)),
+    ("fuchsia", (255, 0, 255, 255)),
+    ("purple", (128, 0, 128, 255)),
+];
+
+/// Parses any color attribute value:
+/// - `{"r", "g", "b", "a"}` with integers in 0..=255, or with floats in 0.0..=1.0 if any component is fractional;
+///   `a` is optional and defaults to opaque,
+/// - `"#rrggbb"` or `"#rrggbbaa"`,
+/// - a color name such as `"red"` or `"transparent"`.
+pub fn parse_color(value: &Value) -> Result<ColorF, String> {
+    match value {
+        Value::Object(_) => parse_components(value),
+        Value::String(s) if s.starts_with('#') => parse_hex(s),
+        Value::String(name) => NAMED_COLORS.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, (r, g, b, a))| ColorU::new(*r, *g, *b, *a).into())
+            .ok_or_else(|| format!("unknown color name {:?}", name)),
+        _ => Err("color must be an object with r, g, b and optional a, a \"#rrggbb[aa]\" string or a color name".to_string()),
+    }
+}
+
+fn parse_components(value: &Value) -> Result<ColorF, String> {
+    for field in COMPONENTS.iter() {
+        match &value[field] {
+            Value::Number(_) => (),
+            Value::Null if *field == "a" => (),
+            _ => return Err(format!("color component {:?} must be a number", field)),
+        }
+    }
+    let is_float = COMPONENTS.iter().any(|field| value[field].is_f64());
+    let component = |field: &str| -> Result<f32, String> {
+        if value[field].is_null() {
+            return Ok(1.0);
+        }
+        if is_float {
+            match value[field].as_f64() {
+                Some(v) if v >= 0.0 && v <= 1.0 => Ok(v as f32),
+                _ => Err(format!("color component {:?} is {}, but fractional colors must be in 0.0..=1.0", field, value[field])),
+            }
+        } else {
+            match value[field].as_u64() {
+                Some(v) if v <= 255 => Ok(v as f32 / 255.0),
+                _ => Err(format!("color component {:?} is {}, but integer colors must be in 0..=255", field, value[field])),
+            }
+        }
+    };
+    Ok(ColorF::new(component("r")?, component("g")?, component("b")?, component("a")?))
+}
+
+fn parse_hex(s: &str) -> Result<ColorF, String> {
+    let digits = &s[1..];
+    if (digits.len() != 6 && digits.len() != 8) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("hex color {:?} must look like \"#rrggbb\" or \"#rrggbbaa\"", s));
+    }
+    let byte = |i: usize| u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).unwrap();
+    let alpha = if digits.len() == 8 { byte(3) } else { 255 };
+    Ok(ColorU::new(byte(0), byte(1), byte(2), alpha).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: Value) -> Result<ColorF, String> {
+        parse_color(&value)
+    }
+
+    #[test]
+    fn test_components() {
+        assert_eq!(parse(json!({"r": 255, "g": 0, "b": 0, "a": 255})), Ok(ColorF::new(1.0, 0.0, 0.0, 1.0)));
+        assert_eq!(parse(json!({"r": 0, "g": 0, "b": 255})), Ok(ColorF::new(0.0, 0.0, 1.0, 1.0)));
+        assert_eq!(parse(json!({"r": 0.5, "g": 0, "b": 1, "a": 0.25})), Ok(ColorF::new(0.5, 0.0, 1.0, 0.25)));
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(parse(json!("#ff0000")), Ok(ColorF::new(1.0, 0.0, 0.0, 1.0)));
+        assert_eq!(parse(json!("#00FF0000")), Ok(ColorF::new(0.0, 1.0, 0.0, 0.0)));
+        assert_eq!(parse(json!("Blue")), Ok(ColorF::new(0.0, 0.0, 1.0, 1.0)));
+        assert_eq!(parse(json!("transparent")), Ok(ColorF::TRANSPARENT));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |value: Value| parse(value).unwrap_err();
+        assert!(error(json!({"r": 256, "g": 0, "b": 0})).contains("0..=255"));
+        assert!(error(json!({"r": 1.5, "g": 0, "b": 0})).contains("0.0..=1.0"));
+        assert!(error(json!({"r": 0, "g": "0", "b": 0})).contains("\"g\" must be a number"));
+        assert!(error(json!({"r": 0, "g": 0})).contains("\"b\" must be a number"));
+        assert!(error(json!("#fff")).contains("#rrggbb"));
+        assert!(error(json!("#gg0000")).contains("#rrggbb"));
+        assert!(error(json!("blurple")).contains("unknown color name"));
+        assert!(error(json!(42)).contains("color must be"));
+    }
+}
@@ -0,0 +1,314 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::codec::{Frame, FrameReader, FrameWriter};
+use crate::handshake::{handshake, HandshakeError, Session};
+use crate::transport::Encoding;
+
+pub type ReadHalf = Box<dyn Read + Send>;
+pub type WriteHalf = Box<dyn WriteStream>;
+
+/// Write side of a connection. Shutting it down also ends reads from the matching `ReadHalf`.
+pub trait WriteStream: Write + Send {
+    fn shutdown(&self) -> io::Result<()>;
+
+    /// Another handle to the same connection, which can still shut it down once this one is dropped.
+    fn shutdown_handle(&self) -> io::Result<WriteHalf>;
+}
+
+impl WriteStream for TcpStream {
+    fn shutdown(&self) -> io::Result<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn shutdown_handle(&self) -> io::Result<WriteHalf> {
+        Ok(Box::new(self.try_clone()?))
+    }
+}
+
+#[cfg(unix)]
+impl WriteStream for UnixStream {
+    fn shutdown(&self) -> io::Result<()> {
+        UnixStream::shutdown(self, Shutdown::Both)
+    }
+
+    fn shutdown_handle(&self) -> io::Result<WriteHalf> {
+        Ok(Box::new(self.try_clone()?))
+    }
+}
+
+/// Way of reaching the server; every call to `connect` opens a fresh connection.
+pub trait Transport: Send + 'static {
+    fn connect(&self) -> io::Result<(ReadHalf, WriteHalf)>;
+
+    /// Whether it makes sense to call `connect` again after the connection is lost.
+    fn can_reconnect(&self) -> bool {
+        true
+    }
+}
+
+impl Transport for Box<dyn Transport> {
+    fn connect(&self) -> io::Result<(ReadHalf, WriteHalf)> {
+        (**self).connect()
+    }
+
+    fn can_reconnect(&self) -> bool {
+        (**self).can_reconnect()
+    }
+}
+
+pub struct TcpTransport {
+    addrs: Vec<SocketAddr>,
+}
+
+impl TcpTransport {
+    pub fn new<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        // resolve once so reconnects don't depend on name lookup
+        Ok(TcpTransport { addrs: addr.to_socket_addrs()?.collect() })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn connect(&self) -> io::Result<(ReadHalf, WriteHalf)> {
+        let stream = TcpStream::connect(self.addrs.as_slice())?;
+        stream.set_nodelay(true)?;
+        let read_half: ReadHalf = Box::new(stream.try_clone()?);
+        Ok((read_half, Box::new(stream)))
+    }
+}
+
+#[cfg(unix)]
+pub struct UnixTransport {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixTransport {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        UnixTransport { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    fn connect(&self) -> io::Result<(ReadHalf, WriteHalf)> {
+        let stream = UnixStream::connect(&self.path)?;
+        let read_half: ReadHalf = Box::new(stream.try_clone()?);
+        Ok((read_half, Box::new(stream)))
+    }
+}
+
+pub fn connect<T: Transport>(transport: &T) -> Result<(FrameReader<ReadHalf>, FrameWriter<WriteHalf>, Session), HandshakeError> {
+    let (read_half, write_half) = transport.connect()?;
+    let mut writer = FrameWriter::new(write_half);
+    let mut reader = FrameReader::new(read_half);
+    let session = handshake(&mut reader, &mut writer)?;
+    Ok((reader, writer, session))
+}
+
+/// Exponential delay between reconnection attempts.
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Backoff { initial, max, current: initial }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = std::cmp::min(self.current * 2, self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff::new(Duration::from_millis(100), Duration::from_secs(5))
+    }
+}
+
+/// Write half of the current server connection, shared by the Noria thread and controllers.
+/// Messages sent while disconnected are dropped.
+#[derive(Clone, Default)]
+pub struct Outgoing(Arc<Mutex<OutgoingState>>);
+
+#[derive(Default)]
+struct OutgoingState {
+    writer: Option<(FrameWriter<WriteHalf>, Encoding)>,
+    /// Kept after a failed write drops `writer`, so `close` can still end reads from the connection.
+    closer: Option<WriteHalf>,
+}
+
+impl Outgoing {
+    pub fn connect(&self, writer: FrameWriter<WriteHalf>, encoding: Encoding) {
+        let closer = match writer.get_ref().shutdown_handle() {
+            Ok(closer) => Some(closer),
+            Err(e) => {
+                log::warn!("Can't keep shutdown handle of connection to Noria: {}", e);
+                None
+            }
+        };
+        *self.0.lock().unwrap() = OutgoingState { writer: Some((writer, encoding)), closer };
+    }
+
+    pub fn disconnect(&self) {
+        *self.0.lock().unwrap() = OutgoingState::default();
+    }
+
+    /// Shuts the current connection down, waking up whoever is blocked reading from it.
+    pub fn close(&self) {
+        let state = std::mem::replace(&mut *self.0.lock().unwrap(), OutgoingState::default());
+        let result = match (&state.closer, &state.writer) {
+            (Some(closer), _) => closer.shutdown(),
+            (None, Some((writer, _))) => writer.get_ref().shutdown(),
+            (None, None) => Ok(()),
+        };
+        if let Err(e) = result {
+            log::warn!("Can't shut down connection to Noria: {}", e);
+        }
+    }
+
+    pub fn send<T: Serialize>(&self, msg: &T) -> bool {
+        let mut state = self.0.lock().unwrap();
+        let result = match state.writer.as_mut() {
+            Some((writer, encoding)) => writer.write_frame(&Frame(encoding.encode(msg))),
+            None => return false,
+        };
+        if let Err(e) = result {
+            log::warn!("Can't send message to Noria: {}", e);
+            state.writer = None;
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(350));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(200));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(350));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(350));
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+    }
+
+    fn echo_over<T: Transport, S: Read + Write>(transport: T, server: impl FnOnce() -> S) {
+        let (read_half, write_half) = transport.connect().unwrap();
+        let mut server = server();
+        FrameWriter::new(write_half).write_frame(&Frame(b"ping".to_vec())).unwrap();
+        let frame = FrameReader::new(&mut server).read_frame().unwrap().unwrap();
+        FrameWriter::new(&mut server).write_frame(&frame).unwrap();
+        assert_eq!(FrameReader::new(read_half).read_frame().unwrap(), Some(Frame(b"ping".to_vec())));
+    }
+
+    #[test]
+    fn test_tcp_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = TcpTransport::new(listener.local_addr().unwrap()).unwrap();
+        echo_over(transport, move || listener.accept().unwrap().0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_transport() {
+        use std::os::unix::net::UnixListener;
+        let path = std::env::temp_dir().join(format!("photon-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        echo_over(UnixTransport::new(&path), move || listener.accept().unwrap().0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_outgoing_drops_while_disconnected() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let outgoing = Outgoing::default();
+        assert!(!outgoing.send(&"lost"));
+
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        outgoing.connect(FrameWriter::new(Box::new(client) as WriteHalf), Encoding::Json);
+        assert!(outgoing.clone().send(&"delivered"));
+        let frame = FrameReader::new(server).read_frame().unwrap().unwrap();
+        assert_eq!(frame.payload(), b"\"delivered\"");
+
+        outgoing.disconnect();
+        assert!(!outgoing.send(&"lost"));
+    }
+
+    #[test]
+    fn test_outgoing_close_ends_reads() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = TcpTransport::new(listener.local_addr().unwrap()).unwrap();
+        let (read_half, write_half) = transport.connect().unwrap();
+        let _server = listener.accept().unwrap();
+        let outgoing = Outgoing::default();
+        outgoing.connect(FrameWriter::new(write_half), Encoding::Json);
+
+        let reading = std::thread::spawn(move || FrameReader::new(read_half).read_frame().unwrap());
+        outgoing.close();
+        assert_eq!(reading.join().unwrap(), None);
+        assert!(!outgoing.send(&"lost"));
+    }
+
+    /// Connection whose writes always fail, as if the server went away.
+    struct BrokenWriter(TcpStream);
+
+    impl Write for BrokenWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "broken"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl WriteStream for BrokenWriter {
+        fn shutdown(&self) -> io::Result<()> {
+            WriteStream::shutdown(&self.0)
+        }
+
+        fn shutdown_handle(&self) -> io::Result<WriteHalf> {
+            self.0.shutdown_handle()
+        }
+    }
+
+    #[test]
+    fn test_outgoing_close_after_failed_send() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let _server = listener.accept().unwrap();
+        let read_half = client.try_clone().unwrap();
+        let outgoing = Outgoing::default();
+        outgoing.connect(FrameWriter::new(Box::new(BrokenWriter(client)) as WriteHalf), Encoding::Json);
+        assert!(!outgoing.send(&"lost"));
+
+        let reading = std::thread::spawn(move || FrameReader::new(read_half).read_frame().unwrap());
+        outgoing.close();
+        assert_eq!(reading.join().unwrap(), None);
+    }
+}
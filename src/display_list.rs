@@ -0,0 +1,193 @@
+use serde::{Serialize, Deserialize};
+use webrender::api::*;
+
+type NodeId = u64;
+
+/// Renderer-independent display list that photon builds from the Dom before handing it to webrender.
+/// Items nest like the Dom: every push is matched by a pop.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "item", rename_all = "kebab-case")]
+pub enum DisplayItem {
+    PushStackingContext { rect: LayoutRect },
+    PopStackingContext,
+    /// Clips everything up to the matching pop to `clip` and lets it scroll within `content`.
+    PushScrollFrame { node: NodeId, content: LayoutRect, clip: LayoutRect },
+    PopScrollFrame,
+    /// Clips everything up to the matching pop to `rect` with rounded corners.
+    PushClip { rect: LayoutRect, radius: BorderRadius },
+    PopClip,
+    Rect {
+        rect: LayoutRect,
+        color: ColorF,
+        /// Node that hit tests on this item resolve to.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag: Option<NodeId>,
+    },
+    Border {
+        rect: LayoutRect,
+        widths: LayoutSideOffsets,
+        top: BorderSide,
+        right: BorderSide,
+        bottom: BorderSide,
+        left: BorderSide,
+        radius: BorderRadius,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag: Option<NodeId>,
+    },
+    /// Gradient along the line from `start` to `end`, both relative to the origin of `rect`.
+    LinearGradient {
+        rect: LayoutRect,
+        start: LayoutPoint,
+        end: LayoutPoint,
+        stops: Vec<GradientStop>,
+        extend_mode: ExtendMode,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag: Option<NodeId>,
+    },
+    /// Elliptic gradient around `center`, relative to the origin of `rect`.
+    RadialGradient {
+        rect: LayoutRect,
+        center: LayoutPoint,
+        radius: LayoutSize,
+        stops: Vec<GradientStop>,
+        extend_mode: ExtendMode,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag: Option<NodeId>,
+    },
+    /// Shadow of the box `rect` with rounded corners `radius`, cast outside of it or, if inset, into it.
+    BoxShadow {
+        rect: LayoutRect,
+        offset: LayoutVector2D,
+        color: ColorF,
+        blur: f32,
+        spread: f32,
+        radius: BorderRadius,
+        clip_mode: BoxShadowClipMode,
+    },
+    Text { rect: LayoutRect, color: ColorF, glyphs: Vec<GlyphInstance> },
+}
+
+/// How far a blur reaches, in multiples of the blur radius.
+const BLUR_EXTENT: f32 = 3.0;
+
+fn primitive_info(rect: LayoutRect, tag: Option<NodeId>) -> LayoutPrimitiveInfo {
+    let mut info = LayoutPrimitiveInfo::new(rect);
+    info.tag = tag.map(|node| (node, 0));
+    info
+}
+
+/// Replays `items` into a webrender display list; all text uses `font_instance_key`.
+pub fn build_webrender_display_list(items: &[DisplayItem],
+                                    pipeline_id: PipelineId,
+                                    content_size: LayoutSize,
+                                    font_instance_key: FontInstanceKey) -> DisplayListBuilder {
+    let mut builder = DisplayListBuilder::new(pipeline_id, content_size);
+    let mut space_and_clip_stack = vec![SpaceAndClipInfo::root_scroll(pipeline_id)];
+    for item in items {
+        let space_and_clip = *space_and_clip_stack.last().expect("Unbalanced scroll frames or clips");
+        match item {
+            DisplayItem::PushStackingContext { rect } => {
+                builder.push_simple_stacking_context(&LayoutPrimitiveInfo::new(*rect), space_and_clip.spatial_id);
+            }
+            DisplayItem::PopStackingContext => {
+                builder.pop_stacking_context();
+            }
+            DisplayItem::PushScrollFrame { node, content, clip } => {
+                let scroll_space_and_clip = builder.define_scroll_frame(&space_and_clip,
+                                                                        Some(ExternalScrollId(*node, pipeline_id)),
+                                                                        *content,
+                                                                        *clip,
+                                                                        vec![],
+                                                                        None,
+                                                                        ScrollSensitivity::ScriptAndInputEvents);
+                space_and_clip_stack.push(scroll_space_and_clip);
+            }
+            DisplayItem::PopScrollFrame => {
+                space_and_clip_stack.pop();
+            }
+            DisplayItem::PushClip { rect, radius } => {
+                let clip_id = builder.define_clip(&space_and_clip,
+                                                  *rect,
+                                                  vec![ComplexClipRegion::new(*rect, *radius, ClipMode::Clip)],
+                                                  None);
+                space_and_clip_stack.push(SpaceAndClipInfo { spatial_id: space_and_clip.spatial_id, clip_id });
+            }
+            DisplayItem::PopClip => {
+                space_and_clip_stack.pop();
+            }
+            DisplayItem::Rect { rect, color, tag } => {
+                builder.push_rect(&primitive_info(*rect, *tag), &space_and_clip, *color);
+            }
+            DisplayItem::Border { rect, widths, top, right, bottom, left, radius, tag } => {
+                let details = BorderDetails::Normal(NormalBorder {
+                    left: *left,
+                    right: *right,
+                    top: *top,
+                    bottom: *bottom,
+                    radius: *radius,
+                    do_aa: true
+                });
+                builder.push_border(&primitive_info(*rect, *tag), &space_and_clip, *widths, details);
+            }
+            DisplayItem::LinearGradient { rect, start, end, stops, extend_mode, tag } => {
+                let gradient = builder.create_gradient(*start, *end, stops.clone(), *extend_mode);
+                builder.push_gradient(&primitive_info(*rect, *tag), &space_and_clip, gradient, rect.size, LayoutSize::zero());
+            }
+            DisplayItem::RadialGradient { rect, center, radius, stops, extend_mode, tag } => {
+                let gradient = builder.create_radial_gradient(*center, *radius, stops.clone(), *extend_mode);
+                builder.push_radial_gradient(&primitive_info(*rect, *tag), &space_and_clip, gradient, rect.size, LayoutSize::zero());
+            }
+            DisplayItem::BoxShadow { rect, offset, color, blur, spread, radius, clip_mode } => {
+                // an outset shadow paints outside of the box, so the primitive must cover the shadow too;
+                // webrender draws the blur out to three times its radius
+                let bounds = match clip_mode {
+                    BoxShadowClipMode::Outset => {
+                        let extent = spread.max(0.0) + BLUR_EXTENT * blur;
+                        rect.union(&rect.translate(offset).inflate(extent, extent))
+                    }
+                    BoxShadowClipMode::Inset => *rect,
+                };
+                builder.push_box_shadow(&LayoutPrimitiveInfo::new(bounds),
+                                        &space_and_clip,
+                                        *rect,
+                                        *offset,
+                                        *color,
+                                        *blur,
+                                        *spread,
+                                        *radius,
+                                        *clip_mode);
+            }
+            DisplayItem::Text { rect, color, glyphs } => {
+                builder.push_text(&LayoutPrimitiveInfo::new(*rect),
+                                  &space_and_clip,
+                                  glyphs.as_slice(),
+                                  font_instance_key,
+                                  *color,
+                                  Some(GlyphOptions {
+                                      render_mode: FontRenderMode::Alpha,
+                                      flags: FontInstanceFlags::FONT_SMOOTHING,
+                                  }));
+            }
+        }
+    }
+    builder
+}
+
+/// Compares `items` with `tests/golden/<name>.json`.
+/// Run tests with `UPDATE_GOLDEN=1` to write the current output instead, then review the diff.
+#[cfg(test)]
+pub fn assert_golden(name: &str, items: &[DisplayItem]) {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.json", name));
+    let actual = serde_json::to_string_pretty(items).unwrap() + "\n";
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Can't read {}: {}; run with UPDATE_GOLDEN=1 to create it", path.display(), e));
+    // compared as values so whitespace and key order in hand-edited files don't matter
+    let expected: serde_json::Value = serde_json::from_str(&expected).unwrap();
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&actual).unwrap(), expected,
+               "display list differs from {}:\n{}", path.display(), actual);
+}
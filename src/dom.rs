@@ -1,36 +1,34 @@
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 
-use std::net::{TcpStream, ToSocketAddrs};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use byteorder::{ReadBytesExt, BigEndian};
 use serde_json::Value;
 use webrender::api::*;
 
 use crate::{text};
 use crate::transport::*;
+use crate::codec::{Frame, FrameReader};
+use crate::connection::{self, Backoff, Outgoing, ReadHalf, Transport};
+use crate::handshake::HandshakeError;
+use crate::error::PhotonError;
+use crate::callbacks::{CallbackMessage, CallbackSink};
+use crate::render_backend::{NullBackend, RenderBackend, WebrenderBackend};
+use crate::display_list::{self, DisplayItem};
+use crate::color::parse_color;
+use crate::panics;
 
-use euclid::TypedSize2D;
 use std::sync::{Mutex, Arc};
 use serde::{Serialize};
 use crate::text::FontsManager;
 use thread_profiler::{register_thread_with_profiler};
 
 use thread_profiler::{profile_scope};
-use std::io::{Read, Write};
+use crossbeam::crossbeam_channel::{Receiver, Sender, TryRecvError, RecvTimeoutError};
+use std::thread::JoinHandle;
+use std::panic::AssertUnwindSafe;
 
 type LogId = u64;
 
-fn read_msg(stream: &mut TcpStream) -> Option<Vec<u8>> {
-    let size = stream.read_u32::<BigEndian>().unwrap();
-    let mut buf = vec![0u8; size as usize];
-    if stream.read_exact(&mut buf).is_ok() {
-        Some(buf)
-    } else {
-        None
-    }
-}
-
 fn current_ts() -> u128 {
     SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis()
 }
@@ -56,39 +54,347 @@ const FANCY_GREEN: ColorF = ColorF {
 #[derive(Debug)]
 enum NodeType {
     Root,
-    Div { color: ColorF, rect: LayoutRect, on_click: Callback, on_wheel: Callback },
-    Text { text: String, origin: LayoutPoint, layouted_text: Option<text::LayoutedText>, color: ColorF },
+    Div { color: ColorF,
+          rect: LayoutRect,
+          background: Background,
+          border_widths: Sides<f32>,
+          border_colors: Sides<ColorF>,
+          border_styles: Sides<BorderStyle>,
+          radius: BorderRadius,
+          box_shadows: Vec<BoxShadow>,
+          on_click: Callback,
+          on_wheel: Callback },
+    Text { text: String, origin: LayoutPoint, layouted_text: Option<text::LayoutedText>, color: ColorF, highlights: Vec<Highlight> },
     Scroll { position: LayoutRect,
              content: LayoutRect,
              on_wheel: Callback },
 }
 
-fn parse_rect(value: &Value) -> LayoutRect {
-    euclid::rect(value["x"].as_f64().unwrap() as f32,
-                 value["y"].as_f64().unwrap() as f32,
-                 value["width"].as_f64().unwrap() as f32,
-                 value["height"].as_f64().unwrap() as f32)
+/// Fill of a div; gradient coordinates are relative to the div's origin.
+#[derive(Debug, Clone, PartialEq)]
+enum Background {
+    Color(ColorF),
+    /// `angle` in degrees as in CSS: 0 points up, 90 to the right.
+    LinearGradient { angle: f32, stops: Vec<GradientStop>, extend_mode: ExtendMode },
+    /// Centered on the div unless `center` is given.
+    RadialGradient { center: Option<LayoutPoint>, radius: LayoutSize, stops: Vec<GradientStop>, extend_mode: ExtendMode },
+}
+
+impl Background {
+    fn display_item(&self, rect: LayoutRect, tag: Option<NodeId>) -> Option<DisplayItem> {
+        match self {
+            Background::Color(color) if color.a > 0.0 => Some(DisplayItem::Rect { rect, color: *color, tag }),
+            Background::Color(_) => None,
+            Background::LinearGradient { angle, stops, extend_mode } => {
+                // the gradient line passes through the center and is just long enough to reach the corners
+                let (sin, cos) = angle.to_radians().sin_cos();
+                let direction = LayoutVector2D::new(sin, -cos);
+                let half_length = (rect.size.width * sin).abs() / 2.0 + (rect.size.height * cos).abs() / 2.0;
+                let center = LayoutPoint::new(rect.size.width / 2.0, rect.size.height / 2.0);
+                Some(DisplayItem::LinearGradient {
+                    rect,
+                    start: center - direction * half_length,
+                    end: center + direction * half_length,
+                    stops: stops.clone(),
+                    extend_mode: *extend_mode,
+                    tag
+                })
+            }
+            Background::RadialGradient { center, radius, stops, extend_mode } => Some(DisplayItem::RadialGradient {
+                rect,
+                center: center.unwrap_or_else(|| LayoutPoint::new(rect.size.width / 2.0, rect.size.height / 2.0)),
+                radius: *radius,
+                stops: stops.clone(),
+                extend_mode: *extend_mode,
+                tag
+            }),
+        }
+    }
+}
+
+/// Shadow cast by or into a div, following its rounded corners.
+#[derive(Debug, Clone, PartialEq)]
+struct BoxShadow {
+    offset: LayoutVector2D,
+    blur: f32,
+    spread: f32,
+    color: ColorF,
+    inset: bool,
+}
+
+/// Background of glyphs `start..end` of a text node.
+#[derive(Debug, Clone, PartialEq)]
+struct Highlight {
+    start: usize,
+    end: usize,
+    color: ColorF,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeTypeSpec {
+    pub name: &'static str,
+    pub attributes: &'static [&'static str],
+    /// Attributes changed with `add`, `remove` and `move`; `children` is implied.
+    /// Those also listed in `attributes` can be replaced as a whole with `set-attr`.
+    #[serde(rename = "list-attributes")]
+    pub list_attributes: &'static [&'static str],
+    pub callbacks: &'static [&'static str],
+}
+
+/// Node types understood by `NodeType::create`, announced to the server during handshake.
+pub const NODE_TYPES: &[NodeTypeSpec] = &[
+    NodeTypeSpec { name: "root", attributes: &[], list_attributes: &[], callbacks: &[] },
+    NodeTypeSpec { name: "div",
+                   attributes: &["color", "rect", "background", "background-color", "border-width", "border-color", "border-style", "border-radius", "box-shadow"], list_attributes: &["box-shadow"], callbacks: &["on-click", "on-wheel"] },
+    NodeTypeSpec { name: "text", attributes: &["text", "origin", "color"], list_attributes: &["highlights"], callbacks: &[] },
+    NodeTypeSpec { name: "scroll", attributes: &["position", "content", "scroll-position"], list_attributes: &[], callbacks: &["on-wheel"] },
+];
+
+/// Per-side value, e.g. of a border.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sides<T> {
+    top: T,
+    right: T,
+    bottom: T,
+    left: T,
+}
+
+impl<T: Copy> Sides<T> {
+    fn uniform(value: T) -> Self {
+        Sides { top: value, right: value, bottom: value, left: value }
+    }
+}
+
+/// Change of a list attribute requested by `add`, `remove` or `move`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListOp {
+    Insert(usize),
+    Remove,
+    Move(usize),
+}
+
+fn parse_f32(value: &Value, field: &str) -> Result<f32, String> {
+    value[field].as_f64()
+        .map(|v| v as f32)
+        .ok_or_else(|| format!("field {:?} must be a number", field))
+}
+
+fn parse_index(value: &Value, field: &str) -> Result<usize, String> {
+    value[field].as_u64()
+        .map(|v| v as usize)
+        .ok_or_else(|| format!("field {:?} must be a non-negative integer", field))
+}
+
+fn parse_highlight(value: &Value) -> Result<Highlight, String> {
+    let highlight = Highlight {
+        start: parse_index(value, "start")?,
+        end: parse_index(value, "end")?,
+        color: parse_color(&value["color"])?,
+    };
+    if highlight.start > highlight.end {
+        return Err(format!("start {} is after end {}", highlight.start, highlight.end));
+    }
+    Ok(highlight)
+}
+
+/// A color, or a gradient object with `type` `"linear"` or `"radial"`, `stops` and an optional `extend-mode`.
+/// Linear gradients take an optional `angle` (180, top to bottom, by default),
+/// radial ones a `radius` and an optional `center`.
+fn parse_background(value: &Value) -> Result<Background, String> {
+    let gradient_type = match value.get("type") {
+        None => return Ok(Background::Color(parse_color(value)?)),
+        Some(gradient_type) => gradient_type,
+    };
+    let stops = parse_gradient_stops(&value["stops"]).map_err(|e| format!("stops: {}", e))?;
+    let extend_mode = match &value["extend-mode"] {
+        Value::Null => ExtendMode::Clamp,
+        Value::String(mode) if mode == "clamp" => ExtendMode::Clamp,
+        Value::String(mode) if mode == "repeat" => ExtendMode::Repeat,
+        _ => return Err("extend-mode: must be \"clamp\" or \"repeat\"".to_string()),
+    };
+    match gradient_type.as_str() {
+        Some("linear") => Ok(Background::LinearGradient {
+            angle: match &value["angle"] {
+                Value::Null => 180.0,
+                angle => angle.as_f64().ok_or_else(|| "angle: must be a number of degrees".to_string())? as f32,
+            },
+            stops,
+            extend_mode
+        }),
+        Some("radial") => Ok(Background::RadialGradient {
+            center: match &value["center"] {
+                Value::Null => None,
+                center => Some(parse_point(center).map_err(|e| format!("center: {}", e))?),
+            },
+            radius: match &value["radius"] {
+                radius @ Value::Object(_) => parse_f32(radius, "width")
+                    .and_then(|width| Ok(LayoutSize::new(width, parse_f32(radius, "height")?))),
+                radius => parse_length(radius).map(|r| LayoutSize::new(r, r)),
+            }.map_err(|e| format!("radius: {}", e))?,
+            stops,
+            extend_mode
+        }),
+        _ => Err("type: gradient type must be \"linear\" or \"radial\"".to_string()),
+    }
+}
+
+/// At least two `{"offset", "color"}` objects with offsets in 0..=1, in increasing order.
+fn parse_gradient_stops(value: &Value) -> Result<Vec<GradientStop>, String> {
+    let stops = match value.as_array() {
+        Some(stops) if stops.len() >= 2 => stops,
+        _ => return Err("must be an array of at least two stops".to_string()),
+    };
+    let mut result: Vec<GradientStop> = Vec::with_capacity(stops.len());
+    for (i, stop) in stops.iter().enumerate() {
+        let offset = parse_f32(stop, "offset").map_err(|e| format!("{}: {}", i, e))?;
+        if offset < 0.0 || offset > 1.0 || result.last().map_or(false, |previous| offset < previous.offset) {
+            return Err(format!("{}: offset {} must be in 0..=1 and not less than the previous one", i, offset));
+        }
+        let color = parse_color(&stop["color"]).map_err(|e| format!("{}: {}", i, e))?;
+        result.push(GradientStop { offset, color });
+    }
+    Ok(result)
+}
+
+/// Array with every item parsed by `parse`, for replacing a list attribute with `set-attr`.
+fn parse_list<T>(value: &Value, parse: impl Fn(&Value) -> Result<T, String>) -> Result<Vec<T>, String> {
+    value.as_array()
+        .ok_or_else(|| "must be an array".to_string())?
+        .iter()
+        .enumerate()
+        .map(|(i, item)| parse(item).map_err(|e| format!("{}: {}", i, e)))
+        .collect()
+}
+
+/// `offset` and `color` are required; `blur` and `spread` default to 0 and `inset` to false.
+fn parse_box_shadow(value: &Value) -> Result<BoxShadow, String> {
+    let optional = |field: &str, parse: &dyn Fn(&Value) -> Result<f32, String>| match &value[field] {
+        Value::Null => Ok(0.0),
+        v => parse(v).map_err(|e| format!("{}: {}", field, e)),
+    };
+    let spread = |v: &Value| v.as_f64().map(|v| v as f32).ok_or_else(|| "must be a number".to_string());
+    Ok(BoxShadow {
+        offset: parse_point(&value["offset"]).map_err(|e| format!("offset: {}", e))?.to_vector(),
+        blur: optional("blur", &parse_length)?,
+        spread: optional("spread", &spread)?,
+        color: parse_color(&value["color"]).map_err(|e| format!("color: {}", e))?,
+        inset: match &value["inset"] {
+            Value::Null => false,
+            Value::Bool(inset) => *inset,
+            _ => return Err("inset: must be a boolean".to_string()),
+        },
+    })
+}
+
+/// Either one value for all sides, or an object with `top`, `right`, `bottom` and `left`.
+fn parse_sides<T: Copy>(value: &Value, parse: impl Fn(&Value) -> Result<T, String>) -> Result<Sides<T>, String> {
+    const SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+    let per_side = value.as_object().map_or(false, |object| SIDES.iter().any(|side| object.contains_key(*side)));
+    if !per_side {
+        return Ok(Sides::uniform(parse(value)?));
+    }
+    let side = |name: &str| parse(&value[name]).map_err(|e| format!("{}: {}", name, e));
+    Ok(Sides { top: side("top")?, right: side("right")?, bottom: side("bottom")?, left: side("left")? })
+}
+
+fn parse_length(value: &Value) -> Result<f32, String> {
+    match value.as_f64() {
+        Some(v) if v >= 0.0 => Ok(v as f32),
+        _ => Err("must be a non-negative number".to_string()),
+    }
+}
+
+fn parse_border_style(value: &Value) -> Result<BorderStyle, String> {
+    match value.as_str() {
+        Some("solid") => Ok(BorderStyle::Solid),
+        Some("dashed") => Ok(BorderStyle::Dashed),
+        Some("dotted") => Ok(BorderStyle::Dotted),
+        Some("double") => Ok(BorderStyle::Double),
+        _ => Err("border style must be one of \"solid\", \"dashed\", \"dotted\" or \"double\"".to_string()),
+    }
+}
+
+/// Either one radius for all corners, or an object with `top-left`, `top-right`, `bottom-right` and `bottom-left`.
+fn parse_border_radius(value: &Value) -> Result<BorderRadius, String> {
+    if !value.is_object() {
+        return Ok(BorderRadius::uniform(parse_length(value)?));
+    }
+    let corner = |name: &str| parse_length(&value[name])
+        .map(|r| LayoutSize::new(r, r))
+        .map_err(|e| format!("{}: {}", name, e));
+    Ok(BorderRadius {
+        top_left: corner("top-left")?,
+        top_right: corner("top-right")?,
+        bottom_right: corner("bottom-right")?,
+        bottom_left: corner("bottom-left")?,
+    })
+}
+
+fn parse_rect(value: &Value) -> Result<LayoutRect, String> {
+    Ok(euclid::rect(parse_f32(value, "x")?,
+                    parse_f32(value, "y")?,
+                    parse_f32(value, "width")?,
+                    parse_f32(value, "height")?))
+}
+
+fn parse_point(value: &Value) -> Result<LayoutPoint, String> {
+    Ok(LayoutPoint::new(parse_f32(value, "x")?,
+                        parse_f32(value, "y")?))
 }
 
-fn parse_point(value: &Value) -> LayoutPoint {
-    LayoutPoint::new(value["x"].as_f64().unwrap() as f32,
-                     value["y"].as_f64().unwrap() as f32)
+fn parse_child(node_id: NodeId, attribute: &str, value: &Value) -> Result<NodeId, PhotonError> {
+    value.as_u64().ok_or_else(|| PhotonError::BadAttributeValue {
+        node: node_id,
+        attribute: attribute.to_string(),
+        value: value.clone(),
+        reason: "child must be a node id".to_string()
+    })
 }
 
+/// Moves the element at `from` so it ends up at `index`, shifting the elements in between by one.
+fn move_item<T>(items: &mut [T], from: usize, index: usize) -> Result<(), String> {
+    if index >= items.len() {
+        return Err(format!("index {} is out of bounds for {} items", index, items.len()));
+    }
+    if from < index {
+        items[from..=index].rotate_left(1);
+    } else {
+        items[index..=from].rotate_right(1);
+    }
+    Ok(())
+}
 
-#[derive(Serialize)]
-struct CallbackMessage<'a, T: Serialize> {
-    log_id: u64,
-    ts: u64,
-    node: NodeId,
-    key: &'a str,
-    arguments: T,
+fn move_child(children: &mut [NodeId], child: NodeId, index: usize) -> Result<(), String> {
+    let from = children.iter().position(|c| *c == child)
+        .ok_or_else(|| format!("node {} is not a child", child))?;
+    move_item(children, from, index)
+}
+
+fn update_list<T: PartialEq>(items: &mut Vec<T>, op: ListOp, item: T) -> Result<(), String> {
+    let position = || items.iter().position(|i| *i == item).ok_or_else(|| "value is not in the list".to_string());
+    match op {
+        ListOp::Insert(index) => {
+            if index > items.len() {
+                return Err(format!("index {} is out of bounds for {} items", index, items.len()));
+            }
+            items.insert(index, item);
+        }
+        ListOp::Remove => {
+            let index = position()?;
+            items.remove(index);
+        }
+        ListOp::Move(index) => {
+            let from = position()?;
+            move_item(items, from, index)?;
+        }
+    }
+    Ok(())
 }
 
 impl NodeType {
-    fn create(node_type: &str) -> NodeType {
-        // TODO support constructor params
-        match node_type {
+    /// Node with default attributes.
+    fn new(node_type: &str) -> Result<NodeType, PhotonError> {
+        let node_type = match node_type {
             "root" => {
                 NodeType::Root
             }
@@ -100,6 +406,7 @@ impl NodeType {
                     origin: default_origin,
                     layouted_text: None,
                     color: ColorF::BLACK,
+                    highlights: Vec::new(),
                 }
             }
             "div" => {
@@ -107,6 +414,12 @@ impl NodeType {
                     color: ColorF::BLACK,
                     rect: LayoutRect::new(LayoutPoint::zero(),
                                           LayoutSize::new(0.0, 0.0)),
+                    background: Background::Color(ColorF::TRANSPARENT),
+                    border_widths: Sides::uniform(1.0),
+                    border_colors: Sides::uniform(ColorF::TRANSPARENT),
+                    border_styles: Sides::uniform(BorderStyle::Solid),
+                    radius: BorderRadius::uniform(3.0),
+                    box_shadows: Vec::new(),
                     on_click: Callback::None,
                     on_wheel: Callback::None
                 }
@@ -120,22 +433,64 @@ impl NodeType {
                     on_wheel: Callback::None
                 }
             }
-            _ => unreachable!("Unknown type {}", node_type)
+            _ => return Err(PhotonError::UnknownType(node_type.to_string()))
+        };
+        Ok(node_type)
+    }
+
+    /// Node with `attributes` applied, or an error without side effects on `backend`.
+    fn create(backend: &mut dyn RenderBackend, node_id: NodeId, node_type: &str, attributes: &serde_json::Map<String, Value>) -> Result<NodeType, PhotonError> {
+        // validate every attribute on a scratch node first, so a failing make-node doesn't layout or scroll
+        let mut scratch = NodeType::new(node_type)?;
+        for (attribute, value) in attributes {
+            scratch.set_attr(&mut NullBackend, node_id, attribute, value)?;
+        }
+        let mut node_type = NodeType::new(node_type)?;
+        for (attribute, value) in attributes {
+            node_type.set_attr(backend, node_id, attribute, value)?;
         }
+        Ok(node_type)
     }
 
-    fn set_attr(&mut self, context: &mut ApplyUpdatesContext, node_id: NodeId, attribute: &str, value: &Value) -> bool {
+    fn set_attr(&mut self, backend: &mut dyn RenderBackend, node_id: NodeId, attribute: &str, value: &Value) -> Result<bool, PhotonError> {
+        let bad_value = |reason: String| PhotonError::BadAttributeValue {
+            node: node_id,
+            attribute: attribute.to_string(),
+            value: value.clone(),
+            reason
+        };
         match self {
             NodeType::Root => {
 
             }
-            NodeType::Div { ref mut color, rect, on_click, on_wheel } => {
+            NodeType::Div { ref mut color, rect, background, border_widths, border_colors, border_styles, radius, box_shadows, .. } => {
                 match attribute {
                     "color" => {
-                        *color = ColorF::WHITE; // parse color
+                        *color = parse_color(value).map_err(bad_value)?;
                     }
                     "rect" => {
-                        *rect = parse_rect(value);
+                        *rect = parse_rect(value).map_err(bad_value)?;
+                    }
+                    "background" => {
+                        *background = parse_background(value).map_err(bad_value)?;
+                    }
+                    "background-color" => {
+                        *background = Background::Color(parse_color(value).map_err(bad_value)?);
+                    }
+                    "border-width" => {
+                        *border_widths = parse_sides(value, parse_length).map_err(bad_value)?;
+                    }
+                    "border-color" => {
+                        *border_colors = parse_sides(value, parse_color).map_err(bad_value)?;
+                    }
+                    "border-style" => {
+                        *border_styles = parse_sides(value, parse_border_style).map_err(bad_value)?;
+                    }
+                    "border-radius" => {
+                        *radius = parse_border_radius(value).map_err(bad_value)?;
+                    }
+                    "box-shadow" => {
+                        *box_shadows = parse_list(value, parse_box_shadow).map_err(bad_value)?;
                     }
 
                     _ => ()
@@ -144,49 +499,81 @@ impl NodeType {
             NodeType::Scroll { ref mut position, content, on_wheel } => {
                 match attribute {
                     "position" => {
-                        *position = parse_rect(value);
+                        *position = parse_rect(value).map_err(bad_value)?;
                     }
                     "content" => {
-                        *content = parse_rect(value);
+                        *content = parse_rect(value).map_err(bad_value)?;
                     }
                     "scroll-position" => {
-                        let x = value["x"].as_f64().unwrap() as f32;
-                        let y = value["y"].as_f64().unwrap() as f32;
-                        context.txn.scroll_node_with_id(LayoutPoint::new(x, y), ExternalScrollId(node_id, context.pipeline_id), ScrollClamping::ToContentBounds);
-                        return false;
+                        let point = parse_point(value).map_err(bad_value)?;
+                        backend.scroll_node(node_id, point);
+                        return Ok(false);
                     }
 
                     _ => ()
                 }
             }
-            NodeType::Text { ref mut text, origin, layouted_text, color } => {
+            NodeType::Text { ref mut text, origin, layouted_text, color, .. } => {
                 match attribute {
                     "text" => {
-                        *text = value.as_str().unwrap().to_string();
-                        *layouted_text = Some(context.fonts_manager.layout_simple_ascii(text));
+                        *text = value.as_str().ok_or_else(|| bad_value("text must be a string".to_string()))?.to_string();
+                        *layouted_text = Some(backend.layout_text(text));
                     }
                     "origin" => {
-                        *origin = parse_point(value);
+                        *origin = parse_point(value).map_err(bad_value)?;
                     }
                     "color" => {
-                        *color = ColorU::new(value["r"].as_u64().unwrap() as u8,
-                                             value["g"].as_u64().unwrap() as u8,
-                                             value["b"].as_u64().unwrap() as u8,
-                                             value["a"].as_u64().unwrap() as u8).into();
+                        *color = parse_color(value).map_err(bad_value)?;
                     }
                     _ => ()
                 }
             }
         }
-        return true;
+        return Ok(true);
+    }
+
+    fn spec(&self) -> &'static NodeTypeSpec {
+        let name = match self {
+            NodeType::Root => "root",
+            NodeType::Div { .. } => "div",
+            NodeType::Text { .. } => "text",
+            NodeType::Scroll { .. } => "scroll",
+        };
+        NODE_TYPES.iter().find(|spec| spec.name == name).expect("every node type is in NODE_TYPES")
     }
 
-    fn set_callback(&mut self, context: &mut ApplyUpdatesContext, attribute: &str, callback: Callback) {
+    /// Changes a list attribute declared in `NODE_TYPES`, other than `children`.
+    fn update_list(&mut self, node_id: NodeId, attribute: &str, op: ListOp, value: &Value) -> Result<bool, PhotonError> {
+        let bad_value = |reason: String| PhotonError::BadAttributeValue {
+            node: node_id,
+            attribute: attribute.to_string(),
+            value: value.clone(),
+            reason
+        };
+        let spec = self.spec();
+        if !spec.list_attributes.contains(&attribute) {
+            return Err(bad_value(format!("not a list attribute of {} nodes", spec.name)));
+        }
+        match (self, attribute) {
+            (NodeType::Text { highlights, .. }, "highlights") => {
+                let highlight = parse_highlight(value).map_err(bad_value)?;
+                update_list(highlights, op, highlight).map_err(bad_value)?;
+            }
+            (NodeType::Div { box_shadows, .. }, "box-shadow") => {
+                let shadow = parse_box_shadow(value).map_err(bad_value)?;
+                update_list(box_shadows, op, shadow).map_err(bad_value)?;
+            }
+            _ => return Err(bad_value(format!("list attribute of {} nodes is not supported", spec.name))),
+        }
+        Ok(true)
+    }
+
+    fn set_callback(&mut self, attribute: &str, callback: Callback) {
         match self {
             NodeType::Root => {
 
             }
-            NodeType::Div { ref mut color, rect, on_click, on_wheel } => {
+            NodeType::Div { on_click, on_wheel, .. } => {
                 match attribute {
                     "on-click" => {
                         *on_click = callback;
@@ -207,93 +594,81 @@ impl NodeType {
                     _ => ()
                 }
             }
-            NodeType::Text { ref mut text, origin, layouted_text, color } => {}
+            NodeType::Text { .. } => {}
         }
     }
 
     fn visit_down(&self, node_id: NodeId, context: &mut VisitorContext) {
         match self {
             NodeType::Root => {
-                let info = LayoutPrimitiveInfo::new(LayoutRect::new(LayoutPoint::zero(), context.builder.content_size()));
-                let root_space_and_clip = SpaceAndClipInfo::root_scroll(context.builder.pipeline_id);
-                context.space_and_clip_stack.push(root_space_and_clip);
-                context.builder.push_simple_stacking_context(&info, root_space_and_clip.spatial_id);
-            }
-            NodeType::Div { color, rect, on_click, on_wheel } => {
-                let mut info = LayoutPrimitiveInfo::new(*rect);
-                let space_and_clip = context.space_and_clip_stack.last().unwrap();
-                let widths = LayoutSideOffsets::new(1.0, 1.0, 1.0, 1.0);
-                let border_color = ColorF::TRANSPARENT;
-                let border_details = BorderDetails::Normal(NormalBorder {
-                    left: BorderSide {
-                        color: border_color,
-                        style: BorderStyle::Solid
-                    },
-                    right: BorderSide {
-                        color: border_color,
-                        style: BorderStyle::Solid
-                    },
-                    top: BorderSide {
-                        color: border_color,
-                        style: BorderStyle::Solid
-                    },
-                    bottom: BorderSide {
-                        color: border_color,
-                        style: BorderStyle::Solid
-                    },
-
-                    radius: BorderRadius {
-                        top_left: TypedSize2D::new(3.0, 3.0),
-                        top_right: TypedSize2D::new(3.0, 3.0),
-                        bottom_left: TypedSize2D::new(3.0, 3.0),
-                        bottom_right: TypedSize2D::new(3.0, 3.0)
-                    },
-                    do_aa: true
-                });
-                if on_click.is_some() || on_wheel.is_some() {
-                    info.tag = Some((node_id, 0));
+                let rect = LayoutRect::new(LayoutPoint::zero(), context.content_size);
+                context.items.push(DisplayItem::PushStackingContext { rect });
+            }
+            NodeType::Div { rect, background, border_widths, border_colors, border_styles, radius, box_shadows, on_click, on_wheel, .. } => {
+                let border_side = |color: ColorF, style: BorderStyle| BorderSide { color, style };
+                let tag = if on_click.is_some() || on_wheel.is_some() {
+                    Some(node_id)
+                } else {
+                    None
+                };
+                // the first shadow is on top; outset shadows go under the background, inset ones over it
+                let shadows = |inset: bool| box_shadows.iter().rev()
+                    .filter(move |shadow| shadow.inset == inset)
+                    .map(move |shadow| DisplayItem::BoxShadow {
+                        rect: *rect,
+                        offset: shadow.offset,
+                        color: shadow.color,
+                        blur: shadow.blur,
+                        spread: shadow.spread,
+                        radius: *radius,
+                        clip_mode: if shadow.inset { BoxShadowClipMode::Inset } else { BoxShadowClipMode::Outset },
+                    });
+                context.items.extend(shadows(false));
+                if let Some(fill) = background.display_item(*rect, tag) {
+                    let rounded = !radius.is_zero();
+                    if rounded {
+                        context.items.push(DisplayItem::PushClip { rect: *rect, radius: *radius });
+                    }
+                    context.items.push(fill);
+                    if rounded {
+                        context.items.push(DisplayItem::PopClip);
+                    }
                 }
-                context.builder.push_border(&info, &space_and_clip, widths, border_details);
-                context.builder.push_simple_stacking_context(&info, space_and_clip.spatial_id);
+                context.items.extend(shadows(true));
+                context.items.push(DisplayItem::Border {
+                    rect: *rect,
+                    widths: LayoutSideOffsets::new(border_widths.top, border_widths.right, border_widths.bottom, border_widths.left),
+                    top: border_side(border_colors.top, border_styles.top),
+                    right: border_side(border_colors.right, border_styles.right),
+                    bottom: border_side(border_colors.bottom, border_styles.bottom),
+                    left: border_side(border_colors.left, border_styles.left),
+                    radius: *radius,
+                    tag
+                });
+                context.items.push(DisplayItem::PushStackingContext { rect: *rect });
             }
             NodeType::Scroll { position, content, on_wheel, .. } => {
-                let parent_space_and_clip = context.space_and_clip_stack.last().unwrap();
-                let scroll_space_and_clip = context.builder.define_scroll_frame(&parent_space_and_clip,
-                                                                                Some(ExternalScrollId(node_id, context.builder.pipeline_id)),
-                                                                                *content,
-                                                                                *position,
-                                                                                vec![],
-                                                                                None,
-                                                                                webrender::api::ScrollSensitivity::ScriptAndInputEvents);
-                context.space_and_clip_stack.push(scroll_space_and_clip);
-                let mut info = LayoutPrimitiveInfo::new(*content);
-                if on_wheel.is_some() {
-                    info.tag = Some((node_id, 0));
-                }
-                context.builder.push_rect(&info,
-                                          &scroll_space_and_clip,
-                                          ColorF::TRANSPARENT);
-            }
-            NodeType::Text { text, origin, layouted_text, color } => {
-                if let Some(parent_space_and_clip) = context.space_and_clip_stack.last() {
-                    if let Some(layouted_text) = layouted_text {
-                        let info = LayoutPrimitiveInfo::new(LayoutRect::new(*origin, layouted_text.size));
-                        context.builder.push_simple_stacking_context(&info, parent_space_and_clip.spatial_id);
-//                        println!("{:?}", layouted_text);
-                        let info = LayoutPrimitiveInfo::new(LayoutRect::new(LayoutPoint::new(0.0, 0.0), layouted_text.size));
-//                        context.builder.push_rect(&info, &parent_space_and_clip, ColorF::new(0.3, 0.4, 0.2, 0.3));
-                        context.builder.push_text(&info,
-                                                  &parent_space_and_clip,
-                                                  layouted_text.glyphs.as_slice(),
-                                                  context.fonts_manager.font_instance_key,
-                                                  *color,
-                                                  Some(GlyphOptions {
-                                                      render_mode: FontRenderMode::Alpha,
-                                                      flags: FontInstanceFlags::FONT_SMOOTHING,
-                                                  }));
+                context.items.push(DisplayItem::PushScrollFrame { node: node_id, content: *content, clip: *position });
+                context.items.push(DisplayItem::Rect {
+                    rect: *content,
+                    color: ColorF::TRANSPARENT,
+                    tag: if on_wheel.is_some() { Some(node_id) } else { None }
+                });
+            }
+            NodeType::Text { origin, layouted_text, color, highlights, .. } => {
+                if let Some(layouted_text) = layouted_text {
+                    context.items.push(DisplayItem::PushStackingContext { rect: LayoutRect::new(*origin, layouted_text.size) });
+                    let glyph_x = |i: usize| layouted_text.glyphs.get(i).map_or(layouted_text.size.width, |g| g.point.x);
+                    for highlight in highlights {
+                        let rect = LayoutRect::new(LayoutPoint::new(glyph_x(highlight.start), 0.0),
+                                                   LayoutSize::new(glyph_x(highlight.end) - glyph_x(highlight.start), layouted_text.size.height));
+                        context.items.push(DisplayItem::Rect { rect, color: highlight.color, tag: None });
                     }
-                } else {
-                    unreachable!("No parent space and clip");
+                    context.items.push(DisplayItem::Text {
+                        rect: LayoutRect::new(LayoutPoint::zero(), layouted_text.size),
+                        color: *color,
+                        glyphs: layouted_text.glyphs.clone()
+                    });
                 }
             }
         }
@@ -302,69 +677,50 @@ impl NodeType {
     fn visit_up(&self, node_id: NodeId, context: &mut VisitorContext) {
         match self {
             NodeType::Root => {
-                context.builder.pop_stacking_context();
-                assert!(context.space_and_clip_stack.pop().is_some());
+                context.items.push(DisplayItem::PopStackingContext);
             }
 
             NodeType::Div { .. } => {
-                context.builder.pop_stacking_context();
+                context.items.push(DisplayItem::PopStackingContext);
             }
 
             NodeType::Scroll { .. } => {
-                assert!(context.space_and_clip_stack.pop().is_some());
+                context.items.push(DisplayItem::PopScrollFrame);
             }
 
-            NodeType::Text { .. } => {
-                context.builder.pop_stacking_context();
+            NodeType::Text { layouted_text, .. } => {
+                if layouted_text.is_some() {
+                    context.items.push(DisplayItem::PopStackingContext);
+                }
             }
         }
     }
 
-    fn on_click(&self, stream: &mut TcpStream, log_id: u64, node_id: NodeId, point: &LayoutPoint) {
+    /// Callback to fire for a click at `point`, if the node listens to clicks.
+    fn on_click(&self, log_id: u64, node_id: NodeId, point: &LayoutPoint) -> Option<CallbackMessage<'static>> {
         match self {
-            NodeType::Div { on_click, .. } => {
-                if on_click.is_some() {
-                    let msg = CallbackMessage {
-                        node: node_id,
-                        ts: current_ts() as u64,
-                        log_id,
-                        key: "on-click",
-                        arguments: vec![point.x, point.y]
-                    };
-                    stream.write(serde_json::to_string(&msg).unwrap().as_bytes()).unwrap();
-                }
-            }
-            _ => ()
+            NodeType::Div { on_click, .. } if on_click.is_some() => Some(CallbackMessage {
+                node: node_id,
+                ts: current_ts() as u64,
+                log_id,
+                key: "on-click",
+                arguments: vec![point.x, point.y]
+            }),
+            _ => None
         }
     }
 
-    fn on_wheel(&self, stream: &mut TcpStream, log_id: u64, node_id: NodeId, delta: &LayoutVector2D) {
+    /// Callback to fire for scrolling by `delta`, if the node listens to the wheel.
+    fn on_wheel(&self, log_id: u64, node_id: NodeId, delta: &LayoutVector2D) -> Option<CallbackMessage<'static>> {
         match self {
-            NodeType::Scroll { on_wheel, .. } => {
-                if on_wheel.is_some() {
-                    let msg = CallbackMessage {
-                        node: node_id,
-                        ts: current_ts() as u64,
-                        log_id,
-                        key: "on-wheel",
-                        arguments: vec![delta.x, delta.y]
-                    };
-                    stream.write(serde_json::to_string(&msg).unwrap().as_bytes()).unwrap();
-                }
-            }
-            NodeType::Div { on_wheel, .. } => {
-                if on_wheel.is_some() {
-                    let msg = CallbackMessage {
-                        node: node_id,
-                        ts: current_ts() as u64,
-                        log_id,
-                        key: "on-wheel",
-                        arguments: vec![delta.x, delta.y]
-                    };
-                    stream.write(serde_json::to_string(&msg).unwrap().as_bytes()).unwrap();
-                }
-            }
-            _ => ()
+            NodeType::Scroll { on_wheel, .. } | NodeType::Div { on_wheel, .. } if on_wheel.is_some() => Some(CallbackMessage {
+                node: node_id,
+                ts: current_ts() as u64,
+                log_id,
+                key: "on-wheel",
+                arguments: vec![delta.x, delta.y]
+            }),
+            _ => None
         }
     }
 }
@@ -377,25 +733,19 @@ struct Node {
 }
 
 struct VisitorContext<'a> {
-    builder: DisplayListBuilder,
-    space_and_clip_stack: Vec<SpaceAndClipInfo>,
-    api: &'a RenderApi,
+    items: Vec<DisplayItem>,
+    content_size: LayoutSize,
     nodes: &'a FxHashMap<NodeId, Node>,
-    fonts_manager: &'a FontsManager
-}
-
-struct ApplyUpdatesContext<'a> {
-    pipeline_id: PipelineId,
-    txn: &'a mut Transaction,
-    fonts_manager: &'a mut FontsManager
 }
 
 impl Node {
     fn visit(&self, context: &mut VisitorContext) {
         self.node_type.visit_down(self.id, context);
         for child_id in &self.children {
-            let node = context.nodes.get(&child_id).unwrap();
-            node.visit(context);
+            // dangling ids are reported by `Dom::check_integrity`
+            if let Some(node) = context.nodes.get(&child_id) {
+                node.visit(context);
+            }
         }
         self.node_type.visit_up(self.id, context);
     }
@@ -404,75 +754,276 @@ impl Node {
 #[derive(Debug, Default)]
 struct Dom {
     nodes: FxHashMap<NodeId, Node>,
+    /// Parent of every node that is someone's child; a node has at most one.
+    parents: FxHashMap<NodeId, NodeId>,
     root_node: Option<NodeId>,
 }
 
-fn apply_updates(dom: &mut Dom, context: &mut ApplyUpdatesContext, message: &Vec<u8>) -> (bool, Vec<u64>) {
+impl Dom {
+    /// Display list of the tree under the root node.
+    fn display_items(&self, content_size: LayoutSize) -> Vec<DisplayItem> {
+        let mut context = VisitorContext {
+            items: Vec::new(),
+            content_size,
+            nodes: &self.nodes,
+        };
+        if let Some(root_node) = self.root_node.and_then(|id| self.nodes.get(&id)) {
+            root_node.visit(&mut context);
+        }
+        context.items
+    }
+
+    /// Removes the node with its whole subtree and unlinks it from the remaining nodes.
+    fn destroy(&mut self, node_id: NodeId) -> Result<(), PhotonError> {
+        if !self.nodes.contains_key(&node_id) {
+            return Err(PhotonError::UnknownNode(node_id));
+        }
+        if let Some(parent) = self.parents.remove(&node_id).and_then(|parent| self.nodes.get_mut(&parent)) {
+            parent.children.retain(|child| *child != node_id);
+        }
+        let mut pending = vec![node_id];
+        while let Some(id) = pending.pop() {
+            if let Some(node) = self.nodes.remove(&id) {
+                self.parents.remove(&id);
+                if self.root_node == Some(id) {
+                    self.root_node = None;
+                }
+                pending.extend(node.children);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that `child` can be added to `parent` without getting a second parent or becoming its own ancestor,
+    /// which would make rendering recurse forever.
+    fn check_new_child(&self, parent: NodeId, child: NodeId) -> Result<(), PhotonError> {
+        if !self.nodes.contains_key(&child) {
+            return Err(PhotonError::UnknownNode(child));
+        }
+        if let Some(current) = self.parents.get(&child) {
+            return Err(PhotonError::Integrity(format!("node {} is already a child of {}", child, current)));
+        }
+        let mut ancestor = Some(parent);
+        while let Some(id) = ancestor {
+            if id == child {
+                return Err(PhotonError::Integrity(format!("adding node {} to {} would make it its own descendant", child, parent)));
+            }
+            ancestor = self.parents.get(&id).cloned();
+        }
+        Ok(())
+    }
+
+    /// Finds cycles, children that don't exist and extra roots, which would break rendering.
+    fn check_integrity(&self) -> Vec<PhotonError> {
+        let mut violations = Vec::new();
+        let mut ids: Vec<NodeId> = self.nodes.keys().cloned().collect();
+        ids.sort();
+        for id in &ids {
+            for child in &self.nodes[id].children {
+                if !self.nodes.contains_key(child) {
+                    violations.push(PhotonError::Integrity(format!("node {} has unknown child {}", id, child)));
+                }
+            }
+        }
+
+        let roots: Vec<NodeId> = ids.iter().cloned()
+            .filter(|id| match self.nodes[id].node_type { NodeType::Root => true, _ => false })
+            .collect();
+        if roots.len() > 1 {
+            violations.push(PhotonError::Integrity(format!("multiple root nodes {:?}", roots)));
+        }
+        if let Some(root) = self.root_node {
+            if !self.nodes.contains_key(&root) {
+                violations.push(PhotonError::Integrity(format!("root node {} does not exist", root)));
+            }
+        }
+
+        let mut visits = FxHashMap::default();
+        for id in &ids {
+            self.find_cycles(*id, &mut visits, &mut violations);
+        }
+        violations
+    }
+
+    fn find_cycles(&self, id: NodeId, visits: &mut FxHashMap<NodeId, Visit>, violations: &mut Vec<PhotonError>) {
+        match visits.get(&id) {
+            Some(Visit::InProgress) => {
+                violations.push(PhotonError::Integrity(format!("node {} is its own descendant", id)));
+                return;
+            }
+            Some(Visit::Done) => return,
+            None => (),
+        }
+        visits.insert(id, Visit::InProgress);
+        if let Some(node) = self.nodes.get(&id) {
+            for child in &node.children {
+                self.find_cycles(*child, visits, violations);
+            }
+        }
+        visits.insert(id, Visit::Done);
+    }
+}
+
+enum Visit {
+    InProgress,
+    Done,
+}
+
+struct AppliedUpdates {
+    need_rebuild: bool,
+    log_ids: Vec<LogId>,
+    /// Updates that could not be applied, with their index in the batch.
+    errors: Vec<(Option<usize>, PhotonError)>,
+}
+
+fn apply_updates(dom: &mut Dom, backend: &mut dyn RenderBackend, updates: Result<NoriaUpdates, PhotonError>) -> AppliedUpdates {
     profile_scope!("apply updates");
-    let updates = if let Some(updates) = serde_json::from_slice::<NoriaUpdates>(&message).ok() {
-        updates
-    } else {
-        println!("{}", String::from_utf8(message.clone()).unwrap());
-        panic!()
+    let mut applied = AppliedUpdates {
+        need_rebuild: false,
+        log_ids: vec![0; 0],
+        errors: Vec::new(),
     };
-    let mut need_rebuild = false;
-    let mut log_ids = vec![0; 0];
-    for update in updates {
+    let updates = match updates {
+        Ok(updates) => updates,
+        Err(e) => {
+            applied.errors.push((None, e));
+            return applied;
+        }
+    };
+    for (index, update) in updates.into_iter().enumerate() {
         match update {
             UpdateOrLogId::LogIds(ids) => {
-                log_ids = ids;
+                applied.log_ids = ids;
+            }
+            UpdateOrLogId::Malformed(value) => {
+                let reason = serde_json::from_value::<Update>(value.clone()).err()
+                    .map_or_else(|| "not an update".to_string(), |e| e.to_string());
+                applied.errors.push((Some(index), PhotonError::Protocol(format!("malformed update {}: {}", value, reason))));
             }
             UpdateOrLogId::Update(update) => {
-                match update {
-                    Update::MakeNode(MakeNode { node_id, node_type }) => {
-                        let node_type = NodeType::create(node_type.as_str());
-                        if let NodeType::Root = node_type {
-                            dom.root_node = Some(node_id);
-                        }
-                        let mut node = Node {
-                            id: node_id,
-                            node_type: node_type,
-                            children: Vec::new(),
-                        };
-                        dom.nodes.insert(node_id, node);
-                        need_rebuild = true;
-                    }
-                    Update::Destroy(Destroy { node_id }) => {
-                        assert!(dom.nodes.remove(&node_id).is_some());
-                        need_rebuild = true;
-                    }
-                    Update::Add(Add { node_id, attribute, index, value }) => {
-                        if attribute == "children" {
-                            let value = value.as_u64().unwrap();
-                            assert!(dom.nodes.contains_key(&value));
-                            let node = dom.nodes.get_mut(&node_id).unwrap();
-                            node.children.insert(index as usize, value);
-                        }
-                        need_rebuild = true;
-                    }
-                    Update::Remove(Remove { node_id, attribute, value }) => {
-                        if attribute == "children" {
-                            let value = value.as_u64().unwrap();
-                            let node = dom.nodes.get_mut(&node_id).expect(format!("No node with {}", node_id).as_str());
-                            if let Some(index) = node.children.iter().position(|x| *x == value) {
-                                node.children.remove(index as usize);
-                            }
-                        }
-                        need_rebuild = true;
-                    }
-                    Update::SetAttr(SetAttr { node_id, attribute, value }) => {
-                        let node = dom.nodes.get_mut(&node_id).unwrap();
-                        need_rebuild |= node.node_type.set_attr(context, node_id, attribute.as_str(), &value);
-                    }
-                    Update::SetCallback(SetCallback { node_id, attribute, callback }) => {
-                        let node = dom.nodes.get_mut(&node_id).unwrap();
-                        node.node_type.set_callback(context, attribute.as_str(), callback)
-                    }
+                match apply_update(dom, backend, update) {
+                    Ok(need_rebuild) => applied.need_rebuild |= need_rebuild,
+                    Err(e) => applied.errors.push((Some(index), e)),
+                }
+            }
+        }
+    }
+    if cfg!(debug_assertions) {
+        profile_scope!("check integrity");
+        applied.errors.extend(dom.check_integrity().into_iter().map(|e| (None, e)));
+    }
+    applied
+}
+
+fn apply_update(dom: &mut Dom, backend: &mut dyn RenderBackend, update: Update) -> Result<bool, PhotonError> {
+    match update {
+        Update::MakeNode(MakeNode { node_id, node_type, attributes }) => {
+            if dom.nodes.contains_key(&node_id) {
+                return Err(PhotonError::DuplicateNode(node_id));
+            }
+            let node_type = NodeType::create(backend, node_id, node_type.as_str(), &attributes)?;
+            if let NodeType::Root = node_type {
+                dom.root_node = Some(node_id);
+            }
+            let node = Node {
+                id: node_id,
+                node_type: node_type,
+                children: Vec::new(),
+            };
+            dom.nodes.insert(node_id, node);
+            Ok(true)
+        }
+        Update::Destroy(Destroy { node_id }) => {
+            dom.destroy(node_id)?;
+            Ok(true)
+        }
+        Update::Add(Add { node_id, attribute, index, value }) => {
+            if attribute == "children" {
+                let child = parse_child(node_id, &attribute, &value)?;
+                if !dom.nodes.contains_key(&node_id) {
+                    return Err(PhotonError::UnknownNode(node_id));
+                }
+                dom.check_new_child(node_id, child)?;
+                let node = dom.nodes.get_mut(&node_id).ok_or(PhotonError::UnknownNode(node_id))?;
+                if index > node.children.len() {
+                    return Err(PhotonError::BadAttributeValue {
+                        node: node_id,
+                        attribute,
+                        value,
+                        reason: format!("index {} is out of bounds for {} children", index, node.children.len())
+                    });
+                }
+                node.children.insert(index, child);
+                dom.parents.insert(child, node_id);
+                Ok(true)
+            } else {
+                let node = dom.nodes.get_mut(&node_id).ok_or(PhotonError::UnknownNode(node_id))?;
+                node.node_type.update_list(node_id, &attribute, ListOp::Insert(index), &value)
+            }
+        }
+        Update::Remove(Remove { node_id, attribute, value }) => {
+            if attribute == "children" {
+                let child = parse_child(node_id, &attribute, &value)?;
+                let node = dom.nodes.get_mut(&node_id).ok_or(PhotonError::UnknownNode(node_id))?;
+                if let Some(index) = node.children.iter().position(|x| *x == child) {
+                    node.children.remove(index);
+                    dom.parents.remove(&child);
+                }
+                Ok(true)
+            } else {
+                let node = dom.nodes.get_mut(&node_id).ok_or(PhotonError::UnknownNode(node_id))?;
+                node.node_type.update_list(node_id, &attribute, ListOp::Remove, &value)
+            }
+        }
+        Update::Move(Move { node_id, attribute, index, value }) => {
+            if attribute == "children" {
+                let child = parse_child(node_id, &attribute, &value)?;
+                let node = dom.nodes.get_mut(&node_id).ok_or(PhotonError::UnknownNode(node_id))?;
+                if let Err(reason) = move_child(&mut node.children, child, index) {
+                    return Err(PhotonError::BadAttributeValue { node: node_id, attribute, value, reason });
                 }
+                Ok(true)
+            } else {
+                let node = dom.nodes.get_mut(&node_id).ok_or(PhotonError::UnknownNode(node_id))?;
+                node.node_type.update_list(node_id, &attribute, ListOp::Move(index), &value)
             }
         }
+        Update::SetChildren(SetChildren { node_id, children }) => {
+            if let Some(missing) = children.iter().find(|child| !dom.nodes.contains_key(child)) {
+                return Err(PhotonError::UnknownNode(*missing));
+            }
+            if !dom.nodes.contains_key(&node_id) {
+                return Err(PhotonError::UnknownNode(node_id));
+            }
+            let mut listed = FxHashSet::default();
+            for child in &children {
+                if !listed.insert(*child) {
+                    return Err(PhotonError::Integrity(format!("node {} is listed twice", child)));
+                }
+                if dom.parents.get(child) != Some(&node_id) {
+                    dom.check_new_child(node_id, *child)?;
+                }
+            }
+            let node = dom.nodes.get_mut(&node_id).ok_or(PhotonError::UnknownNode(node_id))?;
+            let old_children = std::mem::replace(&mut node.children, children);
+            for child in old_children {
+                dom.parents.remove(&child);
+            }
+            for child in &dom.nodes[&node_id].children {
+                dom.parents.insert(*child, node_id);
+            }
+            Ok(true)
+        }
+        Update::SetAttr(SetAttr { node_id, attribute, value }) => {
+            let node = dom.nodes.get_mut(&node_id).ok_or(PhotonError::UnknownNode(node_id))?;
+            node.node_type.set_attr(backend, node_id, attribute.as_str(), &value)
+        }
+        Update::SetCallback(SetCallback { node_id, attribute, callback }) => {
+            let node = dom.nodes.get_mut(&node_id).ok_or(PhotonError::UnknownNode(node_id))?;
+            node.node_type.set_callback(attribute.as_str(), callback);
+            Ok(false)
+        }
     }
-    return (need_rebuild, log_ids)
 }
 
 pub struct NoriaClient {
@@ -481,11 +1032,187 @@ pub struct NoriaClient {
     pipeline_id: PipelineId,
     document_id: DocumentId,
     content_size: LayoutSize,
+    fonts_manager: FontsManager,
+    outgoing: Outgoing,
+    epoch: Epoch,
+}
+
+enum Command {
+    /// Batches are decoded by whoever received them, so the Updates thread only applies them.
+    Apply { updates: Result<NoriaUpdates, PhotonError>, received_ts: u64, done: Option<Sender<Vec<PhotonError>>> },
+    /// Connection to the server was lost.
+    Reset,
+    Stop,
+}
+
+/// Applies update batches that don't come from the server connection, e.g. pushed from the JVM.
+#[derive(Clone)]
+pub struct UpdatesSender(Sender<Command>);
+
+impl UpdatesSender {
+    /// Decodes the batch in place and blocks until it is applied; returns the updates that failed.
+    pub fn apply(&self, message: &[u8], encoding: Encoding) -> Vec<PhotonError> {
+        let received_ts = current_ts() as u64;
+        let (done, done_recv) = crossbeam::crossbeam_channel::bounded(1);
+        let command = Command::Apply { updates: encoding.decode_updates(message), received_ts, done: Some(done) };
+        if self.0.send(command).is_err() {
+            return vec![PhotonError::Protocol("photon is shut down".to_string())];
+        }
+        done_recv.recv().unwrap_or_else(|_| vec![PhotonError::Protocol("photon is shut down".to_string())])
+    }
+}
+
+/// Forwards update batches from the server until the connection is closed or broken.
+fn read_updates(reader: &mut FrameReader<ReadHalf>, encoding: Encoding, commands: &Sender<Command>) {
+    loop {
+        match reader.read_frame() {
+            Ok(Some(Frame(message))) => {
+                let received_ts = current_ts() as u64;
+                let command = Command::Apply { updates: encoding.decode_updates(&message), received_ts, done: None };
+                if commands.send(command).is_err() {
+                    return;
+                }
+            }
+            Ok(None) => return,
+            Err(e) => {
+                log::error!("Can't read message from Noria: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Body of the Noria thread: forwards batches from `reader`, and when the connection is lost reconnects
+/// and asks for a fresh snapshot, until `stop_recv` is disconnected or `transport` can't reconnect.
+fn serve_connection<T: Transport>(transport: T,
+                                  mut reader: FrameReader<ReadHalf>,
+                                  mut encoding: Encoding,
+                                  outgoing: Outgoing,
+                                  commands: Sender<Command>,
+                                  stop_recv: Receiver<()>,
+                                  mut backoff: Backoff) {
+    loop {
+        read_updates(&mut reader, encoding, &commands);
+        outgoing.disconnect();
+        if let Err(TryRecvError::Disconnected) = stop_recv.try_recv() {
+            return;
+        }
+        if !transport.can_reconnect() {
+            log::info!("Noria stream is over");
+            return;
+        }
+        log::warn!("Lost connection to Noria, reconnecting");
+        let _ = commands.send(Command::Reset);
+        reader = loop {
+            match stop_recv.recv_timeout(backoff.next_delay()) {
+                Err(RecvTimeoutError::Timeout) => (),
+                _ => return,
+            }
+            match connection::connect(&transport) {
+                Ok((reader, writer, session)) => {
+                    log::info!("Reconnected to Noria: {:?}", session);
+                    outgoing.connect(writer, session.encoding);
+                    // shutdown may have missed the writer while it wasn't connected yet
+                    if let Err(TryRecvError::Disconnected) = stop_recv.try_recv() {
+                        outgoing.close();
+                        return;
+                    }
+                    encoding = session.encoding;
+                    outgoing.send(&ClientMessage::SnapshotRequest);
+                    backoff.reset();
+                    break reader;
+                }
+                Err(e) => log::debug!("Can't reconnect to Noria: {}", e),
+            }
+        };
+    }
+}
+
+/// Applies commands on the Updates thread.
+trait Updater {
+    /// Applies a batch and returns the updates that failed.
+    fn apply_message(&mut self, updates: Result<NoriaUpdates, PhotonError>, received_ts: u64) -> Vec<PhotonError>;
+    fn reset(&mut self);
+}
+
+/// Threads serving one server connection, stopped by `Controller::shutdown`.
+struct Workers {
+    stop: Sender<()>,
+    /// None without a server connection, when updates only come through `UpdatesSender`.
+    noria_thread: Option<JoinHandle<()>>,
+    updates_thread: JoinHandle<()>,
+}
+
+impl Workers {
+    /// Starts the Updates thread with the updater built by `make_updater`, and the Noria thread if
+    /// there is a connection to serve: its transport, reader and negotiated encoding.
+    fn spawn<T, U, F>(connection: Option<(T, FrameReader<ReadHalf>, Encoding)>, outgoing: &Outgoing, make_updater: F)
+        -> std::io::Result<(Workers, UpdatesSender)>
+        where T: Transport, U: Updater, F: FnOnce() -> U + Send + 'static {
+        let (commands, commands_recv) = crossbeam::crossbeam_channel::unbounded();
+        let updates_thread = std::thread::Builder::new()
+            .name("Updates thread".to_owned())
+            .spawn(move || {
+                register_thread_with_profiler("Updates thread".to_owned());
+                let mut updater = make_updater();
+                for command in commands_recv.iter() {
+                    match command {
+                        Command::Apply { updates, received_ts, done } => {
+                            let errors = panics::catch(AssertUnwindSafe(|| updater.apply_message(updates, received_ts)))
+                                .unwrap_or_else(|panic| {
+                                    log::error!("Updates thread panicked: {}", panic.message);
+                                    vec![PhotonError::Panic { message: panic.message, backtrace: panic.backtrace }]
+                                });
+                            if let Some(done) = done {
+                                let _ = done.send(errors);
+                            }
+                        }
+                        Command::Reset => updater.reset(),
+                        Command::Stop => return,
+                    }
+                }
+            })?;
+
+        // never sent to, dropped to stop the Noria thread
+        let (stop, stop_recv) = crossbeam::crossbeam_channel::bounded::<()>(0);
+        let noria_thread = match connection {
+            Some((transport, reader, encoding)) => {
+                let thread_outgoing = outgoing.clone();
+                let thread_commands = commands.clone();
+                Some(std::thread::Builder::new()
+                    .name("Noria thread".to_owned())
+                    .spawn(move || {
+                        register_thread_with_profiler("Noria thread".to_owned());
+                        serve_connection(transport, reader, encoding, thread_outgoing, thread_commands, stop_recv, Backoff::default());
+                    })?)
+            }
+            None => None,
+        };
+        Ok((Workers { stop, noria_thread, updates_thread }, UpdatesSender(commands)))
+    }
+
+    fn join(self, outgoing: &Outgoing, updates: &UpdatesSender) {
+        let Workers { stop, noria_thread, updates_thread } = self;
+        drop(stop);
+        outgoing.close();
+        if let Some(noria_thread) = noria_thread {
+            if noria_thread.join().is_err() {
+                log::error!("Noria thread panicked");
+            }
+        }
+        let _ = updates.0.send(Command::Stop);
+        if updates_thread.join().is_err() {
+            log::error!("Updates thread panicked");
+        }
+    }
 }
 
 pub struct Controller {
     dom_mutex: Arc<Mutex<Dom>>,
-    stream: TcpStream,
+    callbacks: Arc<dyn CallbackSink>,
+    outgoing: Outgoing,
+    workers: Arc<Mutex<Option<Workers>>>,
+    updates: UpdatesSender,
     document_id: DocumentId,
     pipeline_id: PipelineId,
     api: RenderApi,
@@ -496,7 +1223,10 @@ impl Clone for Controller {
     fn clone(&self) -> Self {
         Controller {
             dom_mutex: self.dom_mutex.clone(),
-            stream: self.stream.try_clone().expect("Can't clone stream"),
+            callbacks: self.callbacks.clone(),
+            outgoing: self.outgoing.clone(),
+            workers: self.workers.clone(),
+            updates: self.updates.clone(),
             document_id: self.document_id,
             pipeline_id: self.pipeline_id,
             api: self.api.clone_sender().create_api(),
@@ -510,17 +1240,32 @@ fn leak_str(s: String) -> &'static str {
 }
 
 impl Controller {
-    pub fn mouse_click(&mut self, cursor_position: WorldPoint) {
-        let hit_result = self.api.hit_test(self.document_id, Some(self.pipeline_id), cursor_position, HitTestFlags::empty());
-        let dom = self.dom_mutex.lock().unwrap();
-        for item in hit_result.items {
-            let (node_id, _) = item.tag;
-            let node_type = &dom.nodes.get(&node_id).unwrap().node_type;
-            node_type.on_click(&mut self.stream, 0, node_id, &item.point_relative_to_item); // TODO LOG_ID!!
+    pub fn updates(&self) -> UpdatesSender {
+        self.updates.clone()
+    }
 
+    /// Closes the server connection and waits for the threads serving it. Safe to call more than once.
+    pub fn shutdown(&self) {
+        let workers = self.workers.lock().unwrap().take();
+        if let Some(workers) = workers {
+            workers.join(&self.outgoing, &self.updates);
         }
     }
 
+    /// Delivers callbacks to `callbacks` instead of the server.
+    pub fn set_callback_sink(&mut self, callbacks: Arc<dyn CallbackSink>) {
+        self.callbacks = callbacks;
+    }
+
+    pub fn mouse_click(&mut self, cursor_position: WorldPoint) {
+        let hit_result = self.api.hit_test(self.document_id, Some(self.pipeline_id), cursor_position, HitTestFlags::empty());
+        send_callbacks(&self.dom_mutex, &*self.callbacks, |dom| hit_result.items.iter()
+            // hit testing runs against the last rendered frame, which can be older than the dom
+            .filter_map(|item| dom.nodes.get(&item.tag.0)
+                .and_then(|node| node.node_type.on_click(0, item.tag.0, &item.point_relative_to_item))) // TODO LOG_ID!!
+            .collect());
+    }
+
     pub fn mouse_wheel(&mut self, cursor_position: WorldPoint, delta: winit::MouseScrollDelta) {
         self.log_id += 1;
         profile_scope!(leak_str(format!("Mouse wheel {}", self.log_id)));
@@ -530,103 +1275,1057 @@ impl Controller {
             winit::MouseScrollDelta::LineDelta(dx, dy) => LayoutVector2D::new(-dx, -dy * LINE_HEIGHT),
             winit::MouseScrollDelta::PixelDelta(pos) => LayoutVector2D::new(-pos.x as f32, -pos.y as f32),
         };
-        let dom = self.dom_mutex.lock().unwrap();
-        for item in hit_result.items {
-            let (node_id, _) = item.tag;
-            let node_type = &dom.nodes.get(&node_id).unwrap().node_type;
-            node_type.on_wheel(&mut self.stream, self.log_id, node_id, &delta_vector);
-        }
+        let log_id = self.log_id;
+        send_callbacks(&self.dom_mutex, &*self.callbacks, |dom| hit_result.items.iter()
+            .filter_map(|item| dom.nodes.get(&item.tag.0)
+                .and_then(|node| node.node_type.on_wheel(log_id, item.tag.0, &delta_vector)))
+            .collect());
     }
 }
 
-struct TransactionNotificationHandler(Vec<LogId>);
+/// Picks callbacks with `fire` while holding the dom lock, then sends them with the lock released:
+/// a sink may apply updates in response, and the Updates thread needs the lock to apply them.
+fn send_callbacks<F>(dom_mutex: &Mutex<Dom>, callbacks: &dyn CallbackSink, fire: F)
+    where F: FnOnce(&Dom) -> Vec<CallbackMessage<'static>> {
+    let messages = fire(&dom_mutex.lock().unwrap());
+    for msg in &messages {
+        callbacks.send_callback(msg);
+    }
+}
+
+struct TransactionNotificationHandler {
+    log_ids: Vec<LogId>,
+    received_ts: u64,
+    sent_ts: u64,
+    outgoing: Outgoing,
+}
+
 impl NotificationHandler for TransactionNotificationHandler {
     fn notify(&self, when: Checkpoint) {
-        profile_scope!(leak_str(format!("Transaction Notify {:?} {:?}", when, self.0)));
+        profile_scope!(leak_str(format!("Transaction Notify {:?} {:?}", when, self.log_ids)));
+        if let Checkpoint::FrameRendered = when {
+            self.outgoing.send(&ClientMessage::FramePresented {
+                log_ids: self.log_ids.clone(),
+                received_ts: self.received_ts,
+                sent_ts: self.sent_ts,
+                presented_ts: current_ts() as u64,
+            });
+        }
     }
 }
 
 impl NoriaClient {
-    pub fn spawn<A: ToSocketAddrs>(addr: A, sender: RenderApiSender, pipeline_id: PipelineId, document_id: DocumentId, content_size: LayoutSize) -> Controller {
-        let api = sender.create_api();
-
+    /// Without a `transport` photon runs embedded: nothing connects to a server and updates only come
+    /// through `Controller::updates`.
+    pub fn spawn<T: Transport>(transport: Option<T>, sender: RenderApiSender, pipeline_id: PipelineId, document_id: DocumentId, content_size: LayoutSize) -> Result<Controller, HandshakeError> {
         let dom_mutex = Arc::new(Mutex::new(Dom::default()));
+        let outgoing = Outgoing::default();
 
-        let mut updater = NoriaClient {
-            dom_mutex: dom_mutex.clone(),
-            api,
-            pipeline_id,
-            document_id,
-            content_size,
+        let connection = match transport {
+            Some(transport) => {
+                let (reader, writer, session) = connection::connect(&transport)?;
+                log::info!("Connected to Noria: {:?}", session);
+                outgoing.connect(writer, session.encoding);
+                Some((transport, reader, session.encoding))
+            }
+            None => None,
         };
-        let mut stream = TcpStream::connect(addr).expect("No server here!");
-        stream.set_nodelay(true).unwrap();
-        stream.write("{kind : \"webrender\"}".as_bytes()).unwrap();
 
-        let mut read_stream = stream.try_clone().unwrap();
-        std::thread::Builder::new()
-            .name("Noria thread".to_owned())
-            .spawn(move || {
-                register_thread_with_profiler("Noria thread".to_owned());
-                let mut fonts_manager = text::FontsManager::new(updater.api.clone_sender().create_api(), updater.document_id);
-                let mut epoch = Epoch(0);
-                loop {
-                    let msg = read_msg(&mut read_stream);
-                    if let Some(msg) = msg {
-                        let mut dom = updater.dom_mutex.lock().unwrap();
-                        let mut txn = Transaction::new();
-                        let mut context = ApplyUpdatesContext {
-                            pipeline_id: pipeline_id,
-                            fonts_manager: &mut fonts_manager,
-                            txn: &mut txn
-                        };
-                        let (rebuild_display_list, log_ids) = apply_updates(&mut dom, &mut context, &msg);
-                        profile_scope!(leak_str(format!("Send TX {:?}", log_ids)));
-                        if rebuild_display_list {
-                            profile_scope!("rebuild DL");
-                            let builder = updater.build_display_list(&mut dom, &mut fonts_manager);
-                            txn.set_display_list(
-                                epoch,
-                                Some(ColorF::WHITE),
-                                updater.content_size,
-                                builder.finalize(),
-                                true,
-                            );
-
-                        } else {
-                            txn.skip_scene_builder();
-                        }
-                        txn.update_epoch(updater.pipeline_id, epoch);
-                        epoch.0 += 1;
-                        txn.generate_frame();
-                        txn.notify(NotificationRequest::new(Checkpoint::FrameRendered, Box::new(TransactionNotificationHandler(log_ids))));
-                        updater.api.send_transaction(updater.document_id, txn);
-                    } else {
-                        break;
-                    }
-                }
-            });
-        Controller {
+        let thread_dom_mutex = dom_mutex.clone();
+        let thread_outgoing = outgoing.clone();
+        let thread_sender = sender.clone();
+        let (workers, updates) = Workers::spawn(connection, &outgoing, move || {
+            NoriaClient::new(thread_dom_mutex, thread_outgoing, thread_sender, pipeline_id, document_id, content_size)
+        })?;
+        Ok(Controller {
             dom_mutex: dom_mutex,
-            stream: stream,
+            callbacks: Arc::new(outgoing.clone()),
+            outgoing: outgoing,
+            workers: Arc::new(Mutex::new(Some(workers))),
+            updates,
             document_id: document_id,
             pipeline_id: pipeline_id,
             api: sender.create_api(),
             log_id: 0,
+        })
+    }
+
+    fn new(dom_mutex: Arc<Mutex<Dom>>, outgoing: Outgoing, sender: RenderApiSender, pipeline_id: PipelineId, document_id: DocumentId, content_size: LayoutSize) -> Self {
+        let api = sender.create_api();
+        let fonts_manager = text::FontsManager::new(sender.create_api(), document_id);
+        NoriaClient {
+            dom_mutex,
+            api,
+            pipeline_id,
+            document_id,
+            content_size,
+            fonts_manager,
+            outgoing,
+            epoch: Epoch(0),
+        }
+    }
+
+    /// `batch` is the log ids and receive time of the update batch to acknowledge once the frame is rendered.
+    fn send_transaction(&mut self, mut txn: Transaction, batch: Option<(Vec<LogId>, u64)>) {
+        txn.update_epoch(self.pipeline_id, self.epoch);
+        self.epoch.0 += 1;
+        txn.generate_frame();
+        if let Some((log_ids, received_ts)) = batch {
+            txn.notify(NotificationRequest::new(Checkpoint::FrameRendered, Box::new(TransactionNotificationHandler {
+                log_ids,
+                received_ts,
+                sent_ts: current_ts() as u64,
+                outgoing: self.outgoing.clone(),
+            })));
         }
+        self.api.send_transaction(self.document_id, txn);
     }
 
-    fn build_display_list(&self, dom: &Dom, fonts_manager: &mut FontsManager) -> DisplayListBuilder {
-        let mut visitor_context = VisitorContext {
-            nodes: &dom.nodes,
-            builder: DisplayListBuilder::new(self.pipeline_id, self.content_size),
-            space_and_clip_stack: Vec::new(),
-            api: &self.api,
-            fonts_manager: fonts_manager,
+    fn build_display_list(&self, dom: &Dom) -> DisplayListBuilder {
+        let items = dom.display_items(self.content_size);
+        display_list::build_webrender_display_list(&items, self.pipeline_id, self.content_size, self.fonts_manager.font_instance_key)
+    }
+}
+
+impl Updater for NoriaClient {
+    /// Applies a batch, reports failed updates to the server and returns them.
+    fn apply_message(&mut self, updates: Result<NoriaUpdates, PhotonError>, received_ts: u64) -> Vec<PhotonError> {
+        let dom_mutex = self.dom_mutex.clone();
+        let mut dom = dom_mutex.lock().unwrap();
+        let mut txn = Transaction::new();
+        let mut backend = WebrenderBackend {
+            pipeline_id: self.pipeline_id,
+            fonts_manager: &mut self.fonts_manager,
+            txn: &mut txn
         };
-        if let Some(root_node_id) = dom.root_node {
-            dom.nodes.get(&root_node_id).unwrap().visit(&mut visitor_context);
+        let AppliedUpdates { need_rebuild, log_ids, errors } = apply_updates(&mut dom, &mut backend, updates);
+        for (update_index, error) in &errors {
+            log::warn!("Can't apply update {:?} of batch {:?}: {}", update_index, log_ids, error);
+            self.outgoing.send(&ClientMessage::Error {
+                log_ids: log_ids.clone(),
+                update_index: *update_index,
+                error_type: error.kind(),
+                node: error.node(),
+                message: error.to_string(),
+            });
+        }
+        profile_scope!(leak_str(format!("Send TX {:?}", log_ids)));
+        if need_rebuild {
+            profile_scope!("rebuild DL");
+            let builder = self.build_display_list(&dom);
+            txn.set_display_list(
+                self.epoch,
+                Some(ColorF::WHITE),
+                self.content_size,
+                builder.finalize(),
+                true,
+            );
+
+        } else {
+            txn.skip_scene_builder();
         }
-        visitor_context.builder
+        self.send_transaction(txn, Some((log_ids, received_ts)));
+        errors.into_iter().map(|(_, error)| error).collect()
     }
-}
\ No newline at end of file
+
+    /// Forgets everything the server sent and shows an empty frame until the next snapshot arrives.
+    fn reset(&mut self) {
+        let dom_mutex = self.dom_mutex.clone();
+        let mut dom = dom_mutex.lock().unwrap();
+        *dom = Dom::default();
+        let mut txn = Transaction::new();
+        txn.set_display_list(
+            self.epoch,
+            Some(ColorF::WHITE),
+            self.content_size,
+            self.build_display_list(&dom).finalize(),
+            true,
+        );
+        self.send_transaction(txn, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use crate::render_backend::{BackendCall, RecordingBackend};
+
+    /// Applies a JSON batch; returns whether it needs a rebuild and the errors as strings.
+    fn apply(dom: &mut Dom, backend: &mut RecordingBackend, batch: Value) -> (bool, Vec<String>) {
+        let updates = serde_json::from_value::<NoriaUpdates>(batch).map_err(|e| PhotonError::Protocol(e.to_string()));
+        let applied = apply_updates(dom, backend, updates);
+        (applied.need_rebuild, applied.errors.into_iter().map(|(_, e)| e.to_string()).collect())
+    }
+
+    fn apply_ok(dom: &mut Dom, backend: &mut RecordingBackend, batch: Value) -> bool {
+        let (need_rebuild, errors) = apply(dom, backend, batch);
+        assert!(errors.is_empty(), "{:?}", errors);
+        need_rebuild
+    }
+
+    fn make(id: NodeId, node_type: &str) -> Value {
+        json!({"update-type": "make-node", "node": id, "type": node_type})
+    }
+
+    fn add(id: NodeId, index: usize, child: NodeId) -> Value {
+        json!({"update-type": "add", "node": id, "attr": "children", "index": index, "value": child})
+    }
+
+    fn set_attr(id: NodeId, attr: &str, value: Value) -> Value {
+        json!({"update-type": "set-attr", "node": id, "attr": attr, "value": value})
+    }
+
+    /// Root 0 with divs 1, 2 and 3 as children.
+    fn three_divs() -> (Dom, RecordingBackend) {
+        let mut dom = Dom::default();
+        let mut backend = RecordingBackend::default();
+        apply_ok(&mut dom, &mut backend, json!([
+            make(0, "root"), make(1, "div"), make(2, "div"), make(3, "div"),
+            add(0, 0, 1), add(0, 1, 2), add(0, 2, 3)
+        ]));
+        (dom, backend)
+    }
+
+    fn children(dom: &Dom, id: NodeId) -> Vec<NodeId> {
+        dom.nodes[&id].children.clone()
+    }
+
+    #[test]
+    fn test_log_ids_and_malformed_updates() {
+        let mut dom = Dom::default();
+        let updates = serde_json::from_value::<NoriaUpdates>(json!([[7, 8], {"update-type": "teleport"}, make(1, "div")])).unwrap();
+        let applied = apply_updates(&mut dom, &mut RecordingBackend::default(), Ok(updates));
+        assert_eq!(applied.log_ids, vec![7, 8]);
+        assert_eq!(applied.errors.len(), 1);
+        assert_eq!(applied.errors[0].0, Some(1));
+        assert!(dom.nodes.contains_key(&1));
+
+        let applied = apply_updates(&mut dom, &mut RecordingBackend::default(), Err(PhotonError::Protocol("garbage".to_string())));
+        assert_eq!(applied.errors[0].0, None);
+    }
+
+    #[test]
+    fn test_make_node() {
+        let (dom, _) = three_divs();
+        assert_eq!(dom.root_node, Some(0));
+        assert_eq!(children(&dom, 0), vec![1, 2, 3]);
+
+        let mut dom = Dom::default();
+        let mut backend = RecordingBackend::default();
+        let (_, errors) = apply(&mut dom, &mut backend, json!([make(1, "video")]));
+        assert_eq!(errors, vec!["unknown node type \"video\"".to_string()]);
+        assert!(dom.nodes.is_empty());
+
+        let (mut dom, mut backend) = three_divs();
+        apply_ok(&mut dom, &mut backend, json!([make(4, "div"), add(1, 0, 4)]));
+        let (_, errors) = apply(&mut dom, &mut backend, json!([make(1, "text")]));
+        assert_eq!(errors, vec!["node 1 already exists".to_string()]);
+        assert_eq!(children(&dom, 1), vec![4]);
+        match dom.nodes[&1].node_type {
+            NodeType::Div { .. } => (),
+            ref other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_make_node_with_attributes() {
+        let mut dom = Dom::default();
+        let mut backend = RecordingBackend::default();
+        apply_ok(&mut dom, &mut backend, json!([
+            {"update-type": "make-node", "node": 1, "type": "text", "attrs": {"text": "hi", "origin": {"x": 1, "y": 2}}}
+        ]));
+        match &dom.nodes[&1].node_type {
+            NodeType::Text { text, origin, layouted_text, .. } => {
+                assert_eq!(text, "hi");
+                assert_eq!(*origin, LayoutPoint::new(1.0, 2.0));
+                assert!(layouted_text.is_some());
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(backend.calls, vec![BackendCall::LayoutText("hi".to_string())]);
+
+        let (_, errors) = apply(&mut dom, &mut backend, json!([
+            {"update-type": "make-node", "node": 2, "type": "div", "attrs": {"rect": {"x": 0}}}
+        ]));
+        assert_eq!(errors.len(), 1);
+        assert!(!dom.nodes.contains_key(&2), "half-initialized node must not be created");
+
+        backend.calls.clear();
+        let (_, errors) = apply(&mut dom, &mut backend, json!([
+            {"update-type": "make-node", "node": 3, "type": "scroll", "attrs": {"scroll-position": {"x": 0, "y": 5}, "content": {"x": 0}}},
+            {"update-type": "make-node", "node": 4, "type": "text", "attrs": {"text": "hi", "origin": {"y": 2}}}
+        ]));
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(backend.calls.is_empty(), "failed make-node must not scroll or layout: {:?}", backend.calls);
+    }
+
+    #[test]
+    fn test_destroy() {
+        let (mut dom, mut backend) = three_divs();
+        apply_ok(&mut dom, &mut backend, json!([make(4, "div"), add(2, 0, 4), {"update-type": "destroy", "node": 2}]));
+        assert_eq!(children(&dom, 0), vec![1, 3]);
+        assert!(!dom.nodes.contains_key(&4));
+        assert_eq!(sorted_ids(&dom), vec![0, 1, 3]);
+        let (_, errors) = apply(&mut dom, &mut backend, json!([{"update-type": "destroy", "node": 2}]));
+        assert_eq!(errors, vec!["no node with id 2".to_string()]);
+    }
+
+    #[test]
+    fn test_add_and_remove_children() {
+        let (mut dom, mut backend) = three_divs();
+        apply_ok(&mut dom, &mut backend, json!([make(4, "div"), add(0, 1, 4)]));
+        assert_eq!(children(&dom, 0), vec![1, 4, 2, 3]);
+
+        let (_, errors) = apply(&mut dom, &mut backend, json!([add(0, 0, 9), add(0, 9, 1), add(9, 0, 1)]));
+        assert_eq!(errors.len(), 3, "{:?}", errors);
+        assert_eq!(children(&dom, 0), vec![1, 4, 2, 3]);
+
+        apply_ok(&mut dom, &mut backend, json!([{"update-type": "remove", "node": 0, "attr": "children", "value": 4}]));
+        assert_eq!(children(&dom, 0), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_move_children() {
+        let (mut dom, mut backend) = three_divs();
+        let move_to = |child: NodeId, index: usize| json!({"update-type": "move", "node": 0, "attr": "children", "index": index, "value": child});
+        apply_ok(&mut dom, &mut backend, json!([move_to(1, 2)]));
+        assert_eq!(children(&dom, 0), vec![2, 3, 1]);
+        apply_ok(&mut dom, &mut backend, json!([move_to(1, 0)]));
+        assert_eq!(children(&dom, 0), vec![1, 2, 3]);
+        let (_, errors) = apply(&mut dom, &mut backend, json!([move_to(1, 3), move_to(0, 0)]));
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert_eq!(children(&dom, 0), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_set_children() {
+        let (mut dom, mut backend) = three_divs();
+        apply_ok(&mut dom, &mut backend, json!([{"update-type": "set-children", "node": 0, "value": [3, 1]}]));
+        assert_eq!(children(&dom, 0), vec![3, 1]);
+        let (_, errors) = apply(&mut dom, &mut backend, json!([{"update-type": "set-children", "node": 0, "value": [2, 9]}]));
+        assert_eq!(errors, vec!["no node with id 9".to_string()]);
+        assert_eq!(children(&dom, 0), vec![3, 1]);
+    }
+
+    #[test]
+    fn test_children_stay_a_tree() {
+        let (mut dom, mut backend) = three_divs();
+        let set_children = |id: NodeId, children: Value| json!({"update-type": "set-children", "node": id, "value": children});
+        let (_, errors) = apply(&mut dom, &mut backend, json!([
+            add(1, 0, 1),
+            add(0, 0, 1),
+            add(2, 0, 1),
+            add(1, 0, 0),
+            set_children(1, json!([0])),
+            set_children(0, json!([1, 1]))
+        ]));
+        assert_eq!(errors, vec![
+            "adding node 1 to 1 would make it its own descendant".to_string(),
+            "node 1 is already a child of 0".to_string(),
+            "node 1 is already a child of 0".to_string(),
+            "adding node 0 to 1 would make it its own descendant".to_string(),
+            "adding node 0 to 1 would make it its own descendant".to_string(),
+            "node 1 is listed twice".to_string(),
+        ].into_iter().map(|e| format!("dom integrity violation: {}", e)).collect::<Vec<_>>());
+        assert_eq!(children(&dom, 0), vec![1, 2, 3]);
+        assert!(children(&dom, 1).is_empty());
+
+        // children given up by set-children can be added elsewhere
+        apply_ok(&mut dom, &mut backend, json!([set_children(0, json!([3, 1])), add(1, 0, 2)]));
+        assert_eq!(children(&dom, 1), vec![2]);
+        let (_, errors) = apply(&mut dom, &mut backend, json!([add(2, 0, 0)]));
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        apply_ok(&mut dom, &mut backend, json!([{"update-type": "remove", "node": 1, "attr": "children", "value": 2}, add(3, 0, 2)]));
+        assert_eq!(children(&dom, 3), vec![2]);
+    }
+
+    #[test]
+    fn test_div_attributes() {
+        let (mut dom, mut backend) = three_divs();
+        assert!(apply_ok(&mut dom, &mut backend, json!([
+            set_attr(1, "color", json!({"r": 255, "g": 0, "b": 0, "a": 255})),
+            set_attr(1, "rect", json!({"x": 1, "y": 2, "width": 3, "height": 4}))
+        ])));
+        match &dom.nodes[&1].node_type {
+            NodeType::Div { color, rect, .. } => {
+                assert_eq!(*color, ColorF::new(1.0, 0.0, 0.0, 1.0));
+                assert_eq!(*rect, LayoutRect::new(LayoutPoint::new(1.0, 2.0), LayoutSize::new(3.0, 4.0)));
+            }
+            other => panic!("{:?}", other),
+        }
+        apply_ok(&mut dom, &mut backend, json!([set_attr(1, "color", json!("#0000ff80"))]));
+        match &dom.nodes[&1].node_type {
+            NodeType::Div { color, .. } => assert_eq!(*color, ColorF::from(ColorU::new(0, 0, 255, 128))),
+            other => panic!("{:?}", other),
+        }
+        let (_, errors) = apply(&mut dom, &mut backend, json!([
+            set_attr(1, "color", json!({"r": 256, "g": 0, "b": 0, "a": 0})),
+            set_attr(1, "rect", json!({"x": 1}))
+        ]));
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+    }
+
+    #[test]
+    fn test_div_background() {
+        let (mut dom, mut backend) = three_divs();
+        let rect = json!({"x": 0, "y": 0, "width": 10, "height": 10});
+        apply_ok(&mut dom, &mut backend, json!([
+            set_attr(1, "rect", rect.clone()),
+            set_attr(2, "rect", rect),
+            set_attr(2, "background-color", json!("red")),
+            {"update-type": "set-callback", "node": 2, "attr": "on-click", "value": "noria-handler-sync"}
+        ]));
+        let items = dom.display_items(LayoutSize::new(100.0, 100.0));
+        let rect = LayoutRect::new(LayoutPoint::zero(), LayoutSize::new(10.0, 10.0));
+        let fills: Vec<&DisplayItem> = items.iter()
+            .filter(|item| match item { DisplayItem::Rect { .. } | DisplayItem::PushClip { .. } | DisplayItem::PopClip => true, _ => false })
+            .collect();
+        assert_eq!(fills, vec![
+            &DisplayItem::PushClip { rect, radius: BorderRadius::uniform(3.0) },
+            &DisplayItem::Rect { rect, color: ColorF::new(1.0, 0.0, 0.0, 1.0), tag: Some(2) },
+            &DisplayItem::PopClip,
+        ], "only the div with a background is filled, clipped to its rounded corners and hit-testable");
+    }
+
+    fn background(dom: &Dom) -> DisplayItem {
+        dom.display_items(LayoutSize::new(100.0, 100.0)).into_iter()
+            .find(|item| match item {
+                DisplayItem::Rect { .. } | DisplayItem::LinearGradient { .. } | DisplayItem::RadialGradient { .. } => true,
+                _ => false
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_div_gradient() {
+        let (mut dom, mut backend) = three_divs();
+        let stops = json!([{"offset": 0, "color": "red"}, {"offset": 0.5, "color": "#0000ff"}, {"offset": 1, "color": "red"}]);
+        apply_ok(&mut dom, &mut backend, json!([
+            set_attr(1, "rect", json!({"x": 0, "y": 0, "width": 10, "height": 20})),
+            set_attr(1, "background", json!({"type": "linear", "stops": stops}))
+        ]));
+        let close = |a: LayoutPoint, b: LayoutPoint| (a - b).length() < 1e-4;
+        match background(&dom) {
+            DisplayItem::LinearGradient { start, end, stops, extend_mode, .. } => {
+                assert!(close(start, LayoutPoint::new(5.0, 0.0)) && close(end, LayoutPoint::new(5.0, 20.0)), "{:?} {:?}", start, end);
+                assert_eq!(stops.iter().map(|stop| stop.offset).collect::<Vec<_>>(), vec![0.0, 0.5, 1.0]);
+                assert_eq!(stops[1].color, ColorF::new(0.0, 0.0, 1.0, 1.0));
+                assert_eq!(extend_mode, ExtendMode::Clamp);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        apply_ok(&mut dom, &mut backend, json!([
+            set_attr(1, "rect", json!({"x": 0, "y": 0, "width": 10, "height": 10})),
+            set_attr(1, "background", json!({"type": "linear", "angle": 45, "stops": stops, "extend-mode": "repeat"}))
+        ]));
+        match background(&dom) {
+            DisplayItem::LinearGradient { start, end, extend_mode, .. } => {
+                assert!(close(start, LayoutPoint::new(0.0, 10.0)) && close(end, LayoutPoint::new(10.0, 0.0)), "{:?} {:?}", start, end);
+                assert_eq!(extend_mode, ExtendMode::Repeat);
+            }
+            other => panic!("{:?}", other),
+        }
+
+        apply_ok(&mut dom, &mut backend, json!([set_attr(1, "background", json!({"type": "radial", "radius": 4, "stops": stops}))]));
+        match background(&dom) {
+            DisplayItem::RadialGradient { center, radius, .. } => {
+                assert_eq!(center, LayoutPoint::new(5.0, 5.0));
+                assert_eq!(radius, LayoutSize::new(4.0, 4.0));
+            }
+            other => panic!("{:?}", other),
+        }
+        apply_ok(&mut dom, &mut backend, json!([
+            set_attr(1, "background", json!({"type": "radial", "center": {"x": 0, "y": 1}, "radius": {"width": 2, "height": 3}, "stops": stops}))
+        ]));
+        match background(&dom) {
+            DisplayItem::RadialGradient { center, radius, .. } => {
+                assert_eq!(center, LayoutPoint::new(0.0, 1.0));
+                assert_eq!(radius, LayoutSize::new(2.0, 3.0));
+            }
+            other => panic!("{:?}", other),
+        }
+
+        apply_ok(&mut dom, &mut backend, json!([set_attr(1, "background", json!("red"))]));
+        match background(&dom) {
+            DisplayItem::Rect { color, .. } => assert_eq!(color, ColorF::new(1.0, 0.0, 0.0, 1.0)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_div_gradient_errors() {
+        let (mut dom, mut backend) = three_divs();
+        let stops = json!([{"offset": 0, "color": "red"}, {"offset": 1, "color": "blue"}]);
+        let (_, errors) = apply(&mut dom, &mut backend, json!([
+            set_attr(1, "background", json!({"type": "conic", "stops": stops})),
+            set_attr(1, "background", json!({"type": "linear", "stops": [{"offset": 0, "color": "red"}]})),
+            set_attr(1, "background", json!({"type": "linear", "stops": [{"offset": 0.5, "color": "red"}, {"offset": 0.2, "color": "red"}]})),
+            set_attr(1, "background", json!({"type": "linear", "stops": [{"offset": 0, "color": "red"}, {"offset": 1, "color": "reddish"}]})),
+            set_attr(1, "background", json!({"type": "linear", "stops": stops, "extend-mode": "mirror"})),
+            set_attr(1, "background", json!({"type": "radial", "stops": stops}))
+        ]));
+        assert_eq!(errors.len(), 6, "{:?}", errors);
+        assert!(errors[0].contains("\"linear\" or \"radial\""), "{}", errors[0]);
+        assert!(errors[1].contains("at least two stops"), "{}", errors[1]);
+        assert!(errors[2].contains("1: offset 0.2"), "{}", errors[2]);
+        assert!(errors[3].contains("1: unknown color name"), "{}", errors[3]);
+        assert!(errors[4].contains("extend-mode"), "{}", errors[4]);
+        assert!(errors[5].contains("radius: must be a non-negative number"), "{}", errors[5]);
+        match &dom.nodes[&1].node_type {
+            NodeType::Div { background, .. } => assert_eq!(*background, Background::Color(ColorF::TRANSPARENT)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn border(dom: &Dom, id: NodeId) -> DisplayItem {
+        let rect = match &dom.nodes[&id].node_type {
+            NodeType::Div { rect, .. } => *rect,
+            other => panic!("{:?}", other),
+        };
+        dom.display_items(LayoutSize::new(100.0, 100.0)).into_iter()
+            .find(|item| match item { DisplayItem::Border { rect: r, .. } => *r == rect, _ => false })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_div_border() {
+        let (mut dom, mut backend) = three_divs();
+        apply_ok(&mut dom, &mut backend, json!([
+            set_attr(1, "rect", json!({"x": 0, "y": 0, "width": 10, "height": 10})),
+            set_attr(1, "border-width", json!(2)),
+            set_attr(1, "border-color", json!({"top": "red", "right": "blue", "bottom": "red", "left": "#00ff00"})),
+            set_attr(1, "border-style", json!("dashed")),
+            set_attr(1, "border-radius", json!({"top-left": 4, "top-right": 0, "bottom-right": 2, "bottom-left": 0}))
+        ]));
+        let red = ColorF::new(1.0, 0.0, 0.0, 1.0);
+        match border(&dom, 1) {
+            DisplayItem::Border { widths, top, right, left, radius, .. } => {
+                assert_eq!(widths, LayoutSideOffsets::new(2.0, 2.0, 2.0, 2.0));
+                assert_eq!(top, BorderSide { color: red, style: BorderStyle::Dashed });
+                assert_eq!(right.color, ColorF::new(0.0, 0.0, 1.0, 1.0));
+                assert_eq!(left.color, ColorF::new(0.0, 1.0, 0.0, 1.0));
+                assert_eq!(radius.top_left, LayoutSize::new(4.0, 4.0));
+                assert_eq!(radius.bottom_right, LayoutSize::new(2.0, 2.0));
+                assert_eq!(radius.top_right, LayoutSize::zero());
+            }
+            other => panic!("{:?}", other),
+        }
+
+        apply_ok(&mut dom, &mut backend, json!([
+            set_attr(1, "border-width", json!({"top": 0, "right": 1, "bottom": 3, "left": 1})),
+            set_attr(1, "border-style", json!({"top": "solid", "right": "dotted", "bottom": "double", "left": "solid"})),
+            set_attr(1, "border-radius", json!(5))
+        ]));
+        match border(&dom, 1) {
+            DisplayItem::Border { widths, right, bottom, radius, .. } => {
+                assert_eq!(widths, LayoutSideOffsets::new(0.0, 1.0, 3.0, 1.0));
+                assert_eq!(right.style, BorderStyle::Dotted);
+                assert_eq!(bottom.style, BorderStyle::Double);
+                assert_eq!(radius, BorderRadius::uniform(5.0));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_div_border_errors() {
+        let (mut dom, mut backend) = three_divs();
+        let (_, errors) = apply(&mut dom, &mut backend, json!([
+            set_attr(1, "border-width", json!(-1)),
+            set_attr(1, "border-width", json!({"top": 1, "right": 1})),
+            set_attr(1, "border-style", json!("groove")),
+            set_attr(1, "border-color", json!({"top": "red", "right": "red", "bottom": "red", "left": "reddish"})),
+            set_attr(1, "border-radius", json!({"top-left": 1}))
+        ]));
+        assert_eq!(errors.len(), 5, "{:?}", errors);
+        assert!(errors[1].contains("bottom: must be a non-negative number"), "{}", errors[1]);
+        assert!(errors[3].contains("left: unknown color name"), "{}", errors[3]);
+        match border(&dom, 1) {
+            DisplayItem::Border { widths, .. } => assert_eq!(widths, LayoutSideOffsets::new(1.0, 1.0, 1.0, 1.0)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_div_box_shadow() {
+        let (mut dom, mut backend) = three_divs();
+        let drop = json!({"offset": {"x": 0, "y": 2}, "blur": 4, "color": "black"});
+        let glow = json!({"offset": {"x": 0, "y": 0}, "spread": -1, "color": "#ffffff80", "inset": true});
+        let far = json!({"offset": {"x": 5, "y": 5}, "color": "gray"});
+        apply_ok(&mut dom, &mut backend, json!([
+            set_attr(1, "rect", json!({"x": 0, "y": 0, "width": 10, "height": 10})),
+            set_attr(1, "background-color", json!("red")),
+            {"update-type": "add", "node": 1, "attr": "box-shadow", "index": 0, "value": drop},
+            {"update-type": "add", "node": 1, "attr": "box-shadow", "index": 1, "value": glow},
+            {"update-type": "add", "node": 1, "attr": "box-shadow", "index": 2, "value": far}
+        ]));
+        let kinds: Vec<String> = dom.display_items(LayoutSize::new(100.0, 100.0)).iter()
+            .take_while(|item| match item { DisplayItem::Border { .. } => false, _ => true })
+            .filter_map(|item| match item {
+                DisplayItem::BoxShadow { offset, clip_mode, .. } => Some(format!("{:?} {}", clip_mode, offset.x)),
+                DisplayItem::Rect { .. } => Some("background".to_string()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(kinds, vec!["Outset 5", "Outset 0", "background", "Inset 0"],
+                   "the first shadow is painted last, inset shadows over the background");
+
+        apply_ok(&mut dom, &mut backend, json!([
+            {"update-type": "remove", "node": 1, "attr": "box-shadow", "value": far},
+            {"update-type": "remove", "node": 1, "attr": "box-shadow", "value": glow}
+        ]));
+        let shadows: Vec<DisplayItem> = dom.display_items(LayoutSize::new(100.0, 100.0)).into_iter()
+            .filter(|item| match item { DisplayItem::BoxShadow { .. } => true, _ => false })
+            .collect();
+        assert_eq!(shadows, vec![DisplayItem::BoxShadow {
+            rect: LayoutRect::new(LayoutPoint::zero(), LayoutSize::new(10.0, 10.0)),
+            offset: LayoutVector2D::new(0.0, 2.0),
+            color: ColorF::BLACK,
+            blur: 4.0,
+            spread: 0.0,
+            radius: BorderRadius::uniform(3.0),
+            clip_mode: BoxShadowClipMode::Outset,
+        }]);
+
+        let box_shadows = |dom: &Dom| match &dom.nodes[&1].node_type {
+            NodeType::Div { box_shadows, .. } => box_shadows.iter().map(|shadow| shadow.offset.x).collect::<Vec<_>>(),
+            other => panic!("{:?}", other),
+        };
+        apply_ok(&mut dom, &mut backend, json!([set_attr(1, "box-shadow", json!([far, glow]))]));
+        assert_eq!(box_shadows(&dom), vec![5.0, 0.0]);
+        apply_ok(&mut dom, &mut backend, json!([set_attr(1, "box-shadow", json!([]))]));
+        assert!(box_shadows(&dom).is_empty());
+    }
+
+    #[test]
+    fn test_div_box_shadow_in_scroll() {
+        let mut dom = Dom::default();
+        let mut backend = RecordingBackend::default();
+        apply_ok(&mut dom, &mut backend, json!([
+            make(0, "root"),
+            {"update-type": "make-node", "node": 1, "type": "scroll", "attrs": {
+                "position": {"x": 0, "y": 0, "width": 100, "height": 50},
+                "content": {"x": 0, "y": 0, "width": 100, "height": 200}
+            }},
+            {"update-type": "make-node", "node": 2, "type": "div", "attrs": {
+                "rect": {"x": 10, "y": 100, "width": 20, "height": 20},
+                "box-shadow": [{"offset": {"x": 0, "y": 2}, "blur": 4, "color": "black"}]
+            }},
+            add(0, 0, 1), add(1, 0, 2)
+        ]));
+        let items = dom.display_items(LayoutSize::new(100.0, 100.0));
+        let position = |matches: &dyn Fn(&DisplayItem) -> bool| items.iter().position(|item| matches(item)).unwrap();
+        let scroll_frame = position(&|item| match item { DisplayItem::PushScrollFrame { node: 1, .. } => true, _ => false });
+        let shadow = position(&|item| match item { DisplayItem::BoxShadow { .. } => true, _ => false });
+        let scroll_frame_end = position(&|item| match item { DisplayItem::PopScrollFrame => true, _ => false });
+        assert!(scroll_frame < shadow && shadow < scroll_frame_end,
+                "the shadow must scroll and be clipped with the scroll frame: {:?}", items);
+    }
+
+    #[test]
+    fn test_div_box_shadow_errors() {
+        let (mut dom, mut backend) = three_divs();
+        let add_shadow = |value: Value| json!({"update-type": "add", "node": 1, "attr": "box-shadow", "index": 0, "value": value});
+        let (_, errors) = apply(&mut dom, &mut backend, json!([
+            add_shadow(json!({"color": "black"})),
+            add_shadow(json!({"offset": {"x": 0, "y": 0}, "blur": -1, "color": "black"})),
+            add_shadow(json!({"offset": {"x": 0, "y": 0}, "color": "black", "inset": "yes"})),
+            add_shadow(json!({"offset": {"x": 0, "y": 0}})),
+            set_attr(1, "box-shadow", json!({"offset": {"x": 0, "y": 0}, "color": "black"})),
+            set_attr(1, "box-shadow", json!([{"offset": {"x": 0, "y": 0}, "color": "black"}, {"color": "black"}]))
+        ]));
+        assert_eq!(errors.len(), 6, "{:?}", errors);
+        assert!(errors[0].contains("offset: field \"x\" must be a number"), "{}", errors[0]);
+        assert!(errors[1].contains("blur: must be a non-negative number"), "{}", errors[1]);
+        assert!(errors[2].contains("inset: must be a boolean"), "{}", errors[2]);
+        assert!(errors[3].contains("color: color must be"), "{}", errors[3]);
+        assert!(errors[4].contains("must be an array"), "{}", errors[4]);
+        assert!(errors[5].contains("1: offset: field"), "{}", errors[5]);
+        match &dom.nodes[&1].node_type {
+            NodeType::Div { box_shadows, .. } => assert!(box_shadows.is_empty()),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_text_attributes() {
+        let mut dom = Dom::default();
+        let mut backend = RecordingBackend::default();
+        apply_ok(&mut dom, &mut backend, json!([
+            make(1, "text"),
+            set_attr(1, "text", json!("hello")),
+            set_attr(1, "origin", json!({"x": 5, "y": 6})),
+            set_attr(1, "color", json!({"r": 0, "g": 0, "b": 255, "a": 255}))
+        ]));
+        match &dom.nodes[&1].node_type {
+            NodeType::Text { text, origin, layouted_text, color, .. } => {
+                assert_eq!(text, "hello");
+                assert_eq!(*origin, LayoutPoint::new(5.0, 6.0));
+                assert_eq!(layouted_text.as_ref().unwrap().size, LayoutSize::new(50.0, RecordingBackend::LINE_HEIGHT));
+                assert_eq!(*color, ColorF::new(0.0, 0.0, 1.0, 1.0));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(backend.calls, vec![BackendCall::LayoutText("hello".to_string())]);
+        let (_, errors) = apply(&mut dom, &mut backend, json!([set_attr(1, "text", json!(42))]));
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_text_highlights() {
+        let mut dom = Dom::default();
+        let mut backend = RecordingBackend::default();
+        let red = json!({"start": 0, "end": 1, "color": {"r": 255, "g": 0, "b": 0, "a": 255}});
+        let blue = json!({"start": 2, "end": 4, "color": {"r": 0, "g": 0, "b": 255, "a": 255}});
+        let highlights = |dom: &Dom| match &dom.nodes[&1].node_type {
+            NodeType::Text { highlights, .. } => highlights.iter().map(|h| h.start).collect::<Vec<_>>(),
+            other => panic!("{:?}", other),
+        };
+        apply_ok(&mut dom, &mut backend, json!([
+            make(1, "text"),
+            {"update-type": "add", "node": 1, "attr": "highlights", "index": 0, "value": red},
+            {"update-type": "add", "node": 1, "attr": "highlights", "index": 0, "value": blue}
+        ]));
+        assert_eq!(highlights(&dom), vec![2, 0]);
+        apply_ok(&mut dom, &mut backend, json!([{"update-type": "move", "node": 1, "attr": "highlights", "index": 1, "value": blue}]));
+        assert_eq!(highlights(&dom), vec![0, 2]);
+        apply_ok(&mut dom, &mut backend, json!([{"update-type": "remove", "node": 1, "attr": "highlights", "value": red}]));
+        assert_eq!(highlights(&dom), vec![2]);
+
+        let (_, errors) = apply(&mut dom, &mut backend, json!([
+            {"update-type": "add", "node": 1, "attr": "decorations", "index": 0, "value": red}
+        ]));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("not a list attribute of text nodes"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn test_list_attributes_match_node_types() {
+        for spec in NODE_TYPES {
+            let mut node_type = NodeType::create(&mut RecordingBackend::default(), 1, spec.name, &serde_json::Map::new()).unwrap();
+            assert_eq!(node_type.spec().name, spec.name);
+            for attribute in spec.list_attributes {
+                let error = node_type.update_list(1, attribute, ListOp::Remove, &Value::Null).unwrap_err().to_string();
+                assert!(!error.contains("list attribute of"), "{} of {}: {}", attribute, spec.name, error);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scroll_attributes() {
+        let mut dom = Dom::default();
+        let mut backend = RecordingBackend::default();
+        apply_ok(&mut dom, &mut backend, json!([
+            make(1, "scroll"),
+            set_attr(1, "position", json!({"x": 0, "y": 0, "width": 100, "height": 50})),
+            set_attr(1, "content", json!({"x": 0, "y": 0, "width": 100, "height": 500}))
+        ]));
+        match &dom.nodes[&1].node_type {
+            NodeType::Scroll { position, content, .. } => {
+                assert_eq!(position.size, LayoutSize::new(100.0, 50.0));
+                assert_eq!(content.size, LayoutSize::new(100.0, 500.0));
+            }
+            other => panic!("{:?}", other),
+        }
+        let need_rebuild = apply_ok(&mut dom, &mut backend, json!([set_attr(1, "scroll-position", json!({"x": 0, "y": 30}))]));
+        assert!(!need_rebuild, "scrolling doesn't change the display list");
+        assert_eq!(backend.calls, vec![BackendCall::ScrollNode(1, LayoutPoint::new(0.0, 30.0))]);
+    }
+
+    #[test]
+    fn test_set_callback() {
+        let mut dom = Dom::default();
+        let mut backend = RecordingBackend::default();
+        let set_callback = |id: NodeId, attr: &str, kind: &str| json!({"update-type": "set-callback", "node": id, "attr": attr, "value": kind});
+        apply_ok(&mut dom, &mut backend, json!([make(1, "div"), make(2, "scroll")]));
+        let need_rebuild = apply_ok(&mut dom, &mut backend, json!([
+            set_callback(1, "on-click", "noria-handler-sync"),
+            set_callback(1, "on-wheel", "noria-handler-async"),
+            set_callback(2, "on-wheel", "noria-handler-sync")
+        ]));
+        assert!(!need_rebuild);
+        match &dom.nodes[&1].node_type {
+            NodeType::Div { on_click, on_wheel, .. } => {
+                assert_eq!(*on_click, Callback::Sync);
+                assert_eq!(*on_wheel, Callback::Async);
+            }
+            other => panic!("{:?}", other),
+        }
+        match &dom.nodes[&2].node_type {
+            NodeType::Scroll { on_wheel, .. } => assert_eq!(*on_wheel, Callback::Sync),
+            other => panic!("{:?}", other),
+        }
+        apply_ok(&mut dom, &mut backend, json!([set_callback(1, "on-click", "-noria-handler")]));
+        match &dom.nodes[&1].node_type {
+            NodeType::Div { on_click, .. } => assert!(!on_click.is_some()),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_display_items_golden() {
+        let mut dom = Dom::default();
+        let mut backend = RecordingBackend::default();
+        apply_ok(&mut dom, &mut backend, json!([
+            make(0, "root"),
+            {"update-type": "make-node", "node": 1, "type": "div", "attrs": {"rect": {"x": 10, "y": 10, "width": 80, "height": 20}}},
+            {"update-type": "set-callback", "node": 1, "attr": "on-click", "value": "noria-handler-sync"},
+            {"update-type": "make-node", "node": 2, "type": "scroll", "attrs": {
+                "position": {"x": 0, "y": 40, "width": 100, "height": 60},
+                "content": {"x": 0, "y": 40, "width": 100, "height": 200}
+            }},
+            {"update-type": "make-node", "node": 3, "type": "text", "attrs": {"text": "ab", "origin": {"x": 5, "y": 45}}},
+            {"update-type": "add", "node": 3, "attr": "highlights", "index": 0,
+             "value": {"start": 1, "end": 2, "color": {"r": 255, "g": 0, "b": 0, "a": 255}}},
+            add(0, 0, 1), add(0, 1, 2), add(2, 0, 3)
+        ]));
+        display_list::assert_golden("div_scroll_text", &dom.display_items(LayoutSize::new(100.0, 100.0)));
+    }
+
+    #[test]
+    fn test_display_items_without_root() {
+        let mut dom = Dom::default();
+        apply_ok(&mut dom, &mut RecordingBackend::default(), json!([make(1, "div")]));
+        assert!(dom.display_items(LayoutSize::new(100.0, 100.0)).is_empty());
+    }
+
+    fn node(id: NodeId, node_type: NodeType, children: Vec<NodeId>) -> Node {
+        Node { id, node_type, children }
+    }
+
+    fn div() -> NodeType {
+        NodeType::create(&mut RecordingBackend::default(), 0, "div", &serde_json::Map::new()).unwrap()
+    }
+
+    fn dom(nodes: Vec<Node>) -> Dom {
+        let root_node = nodes.iter().find(|n| match n.node_type { NodeType::Root => true, _ => false }).map(|n| n.id);
+        let parents = nodes.iter().flat_map(|n| n.children.iter().map(move |child| (*child, n.id))).collect();
+        Dom { nodes: nodes.into_iter().map(|n| (n.id, n)).collect(), parents, root_node }
+    }
+
+    fn sorted_ids(dom: &Dom) -> Vec<NodeId> {
+        let mut ids: Vec<NodeId> = dom.nodes.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_destroy_subtree() {
+        let mut dom = dom(vec![
+            node(0, NodeType::Root, vec![1, 4]),
+            node(1, div(), vec![2, 3]),
+            node(2, div(), vec![]),
+            node(3, div(), vec![]),
+            node(4, div(), vec![]),
+        ]);
+        dom.destroy(1).unwrap();
+        assert_eq!(sorted_ids(&dom), vec![0, 4]);
+        assert_eq!(dom.nodes[&0].children, vec![4]);
+        assert!(dom.check_integrity().is_empty());
+
+        assert!(dom.destroy(1).is_err());
+        dom.destroy(0).unwrap();
+        assert!(dom.nodes.is_empty());
+        assert_eq!(dom.root_node, None);
+    }
+
+    #[test]
+    fn test_destroy_cycle() {
+        let mut dom = dom(vec![
+            node(1, div(), vec![2]),
+            node(2, div(), vec![1]),
+        ]);
+        dom.destroy(2).unwrap();
+        assert!(dom.nodes.is_empty());
+    }
+
+    #[test]
+    fn test_check_integrity() {
+        let dom = dom(vec![
+            node(0, NodeType::Root, vec![1, 5]),
+            node(1, div(), vec![2]),
+            node(2, div(), vec![1]),
+            node(3, NodeType::Root, vec![]),
+        ]);
+        let violations: Vec<String> = dom.check_integrity().iter().map(|e| e.to_string()).collect();
+        assert_eq!(violations.len(), 3, "{:?}", violations);
+        assert!(violations[0].contains("unknown child 5"));
+        assert!(violations[1].contains("multiple root nodes [0, 3]"));
+        assert!(violations[2].contains("node 1 is its own descendant"));
+    }
+
+    fn moved(child: NodeId, index: usize) -> Result<Vec<NodeId>, String> {
+        let mut children = vec![1, 2, 3, 4];
+        move_child(&mut children, child, index).map(|()| children)
+    }
+
+    #[test]
+    fn test_move_child() {
+        assert_eq!(moved(1, 2), Ok(vec![2, 3, 1, 4]));
+        assert_eq!(moved(3, 0), Ok(vec![3, 1, 2, 4]));
+        assert_eq!(moved(2, 3), Ok(vec![1, 3, 4, 2]));
+        assert_eq!(moved(4, 0), Ok(vec![4, 1, 2, 3]));
+        assert_eq!(moved(2, 1), Ok(vec![1, 2, 3, 4]));
+    }
+
+    #[test]
+    fn test_update_list() {
+        let mut items = vec!["a", "b"];
+        update_list(&mut items, ListOp::Insert(2), "c").unwrap();
+        update_list(&mut items, ListOp::Insert(0), "d").unwrap();
+        assert_eq!(items, vec!["d", "a", "b", "c"]);
+        update_list(&mut items, ListOp::Move(3), "a").unwrap();
+        assert_eq!(items, vec!["d", "b", "c", "a"]);
+        update_list(&mut items, ListOp::Remove, "b").unwrap();
+        assert_eq!(items, vec!["d", "c", "a"]);
+        assert!(update_list(&mut items, ListOp::Insert(4), "e").is_err());
+        assert!(update_list(&mut items, ListOp::Remove, "b").is_err());
+        assert!(update_list(&mut items, ListOp::Move(0), "b").is_err());
+    }
+
+    #[test]
+    fn test_parse_highlight() {
+        let highlight = parse_highlight(&serde_json::json!({"start": 2, "end": 5, "color": {"r": 255, "g": 0, "b": 0, "a": 255}})).unwrap();
+        assert_eq!(highlight, Highlight { start: 2, end: 5, color: ColorF::new(1.0, 0.0, 0.0, 1.0) });
+        assert!(parse_highlight(&serde_json::json!({"start": 5, "end": 2, "color": {"r": 0, "g": 0, "b": 0, "a": 0}})).is_err());
+        assert!(parse_highlight(&serde_json::json!({"start": 0, "end": 2})).is_err());
+    }
+
+    #[test]
+    fn test_move_child_errors() {
+        assert!(moved(1, 4).is_err());
+        assert!(moved(5, 0).is_err());
+        let mut children = Vec::new();
+        assert!(move_child(&mut children, 1, 0).is_err());
+    }
+
+    /// Accepts a connection on `listener` and answers the client hello.
+    fn accept_client(listener: &std::net::TcpListener) -> (FrameReader<std::net::TcpStream>, crate::codec::FrameWriter<std::net::TcpStream>) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = FrameReader::new(stream.try_clone().unwrap());
+        let mut writer = crate::codec::FrameWriter::new(stream);
+        reader.read_frame().unwrap().unwrap();
+        writer.write_frame(&Frame(br#"{"version": 1, "encoding": "json"}"#.to_vec())).unwrap();
+        (reader, writer)
+    }
+
+    /// Update types of the next applied batch, or "reset".
+    fn next_command(commands: &Receiver<Command>) -> Vec<String> {
+        match commands.recv_timeout(std::time::Duration::from_secs(5)).expect("no command from the Noria thread") {
+            Command::Apply { updates, .. } => updates.unwrap().into_iter()
+                .map(|update| match update {
+                    UpdateOrLogId::Update(update) => serde_json::to_value(&update).unwrap()["update-type"].as_str().unwrap().to_string(),
+                    _ => "other".to_string(),
+                })
+                .collect(),
+            Command::Reset => vec!["reset".to_string()],
+            Command::Stop => vec!["stop".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_reconnect_and_resync() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = connection::TcpTransport::new(listener.local_addr().unwrap()).unwrap();
+        let server = std::thread::spawn(move || {
+            let (_, mut writer) = accept_client(&listener);
+            writer.write_frame(&Frame(br#"[{"update-type": "make-node", "node": 1, "type": "div"}]"#.to_vec())).unwrap();
+            // dropping both halves loses the connection
+            drop(writer);
+            let (mut reader, mut writer) = accept_client(&listener);
+            let request = reader.read_frame().unwrap().unwrap();
+            writer.write_frame(&Frame(br#"[{"update-type": "make-node", "node": 0, "type": "root"}]"#.to_vec())).unwrap();
+            (request, reader, writer)
+        });
+
+        let (reader, writer, session) = connection::connect(&transport).unwrap();
+        let outgoing = Outgoing::default();
+        outgoing.connect(writer, session.encoding);
+        let (commands, commands_recv) = crossbeam::crossbeam_channel::unbounded();
+        let (stop, stop_recv) = crossbeam::crossbeam_channel::bounded::<()>(0);
+        let thread_outgoing = outgoing.clone();
+        let backoff = Backoff::new(std::time::Duration::from_millis(10), std::time::Duration::from_millis(50));
+        let noria_thread = std::thread::spawn(move || {
+            serve_connection(transport, reader, session.encoding, thread_outgoing, commands, stop_recv, backoff)
+        });
+
+        assert_eq!(next_command(&commands_recv), vec!["make-node"]);
+        assert_eq!(next_command(&commands_recv), vec!["reset"]);
+        assert_eq!(next_command(&commands_recv), vec!["make-node"]);
+        let (request, _reader, _writer) = server.join().unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(request.payload()).unwrap(), json!({"kind": "snapshot-request"}));
+
+        drop(stop);
+        outgoing.close();
+        noria_thread.join().unwrap();
+    }
+
+    /// Applies batches to a Dom shared with the test, without a renderer.
+    struct DomUpdater(Arc<Mutex<Dom>>);
+
+    impl Updater for DomUpdater {
+        fn apply_message(&mut self, updates: Result<NoriaUpdates, PhotonError>, _: u64) -> Vec<PhotonError> {
+            let mut dom = self.0.lock().unwrap();
+            apply_updates(&mut dom, &mut RecordingBackend::default(), updates).errors.into_iter().map(|(_, error)| error).collect()
+        }
+
+        fn reset(&mut self) {
+            *self.0.lock().unwrap() = Dom::default();
+        }
+    }
+
+    #[test]
+    fn test_apply_updates_without_server() {
+        let dom = Arc::new(Mutex::new(Dom::default()));
+        let outgoing = Outgoing::default();
+        let thread_dom = dom.clone();
+        let no_connection: Option<(connection::TcpTransport, FrameReader<ReadHalf>, Encoding)> = None;
+        let (workers, updates) = Workers::spawn(no_connection, &outgoing, move || DomUpdater(thread_dom)).unwrap();
+
+        let batch = json!([make(0, "root"), make(1, "div"), add(0, 0, 1)]).to_string();
+        assert!(updates.apply(batch.as_bytes(), Encoding::Json).is_empty());
+        assert_eq!(dom.lock().unwrap().root_node, Some(0));
+        assert_eq!(children(&dom.lock().unwrap(), 0), vec![1]);
+        let errors = updates.apply(json!([make(1, "div")]).to_string().as_bytes(), Encoding::Json);
+        assert_eq!(errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(), vec!["node 1 already exists"]);
+
+        workers.join(&outgoing, &updates);
+        assert_eq!(updates.apply(batch.as_bytes(), Encoding::Json).len(), 1);
+    }
+
+    /// Responds to every callback by applying a batch, as a JVM listener would.
+    struct ApplyingSink {
+        updates: UpdatesSender,
+        errors: Mutex<Vec<PhotonError>>,
+    }
+
+    impl CallbackSink for ApplyingSink {
+        fn send_callback(&self, msg: &CallbackMessage) {
+            let batch = json!([set_attr(msg.node, "background-color", json!({"r": 255, "g": 0, "b": 0, "a": 255}))]);
+            self.errors.lock().unwrap().extend(self.updates.apply(batch.to_string().as_bytes(), Encoding::Json));
+        }
+    }
+
+    #[test]
+    fn test_callbacks_can_apply_updates() {
+        let dom = Arc::new(Mutex::new(Dom::default()));
+        let outgoing = Outgoing::default();
+        let thread_dom = dom.clone();
+        let no_connection: Option<(connection::TcpTransport, FrameReader<ReadHalf>, Encoding)> = None;
+        let (workers, updates) = Workers::spawn(no_connection, &outgoing, move || DomUpdater(thread_dom)).unwrap();
+        let batch = json!([make(1, "div"), {"update-type": "set-callback", "node": 1, "attr": "on-click", "value": "noria-handler-sync"}]);
+        assert!(updates.apply(batch.to_string().as_bytes(), Encoding::Json).is_empty());
+
+        let sink = ApplyingSink { updates: updates.clone(), errors: Mutex::new(Vec::new()) };
+        send_callbacks(&dom, &sink, |dom| dom.nodes[&1].node_type.on_click(0, 1, &LayoutPoint::new(1.0, 2.0)).into_iter().collect());
+        assert!(sink.errors.lock().unwrap().is_empty());
+        match &dom.lock().unwrap().nodes[&1].node_type {
+            NodeType::Div { background, .. } => assert_eq!(*background, Background::Color(ColorF::new(1.0, 0.0, 0.0, 1.0))),
+            other => panic!("{:?}", other),
+        }
+
+        workers.join(&outgoing, &updates);
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+
+use serde_json::Value;
+
+type NodeId = u64;
+
+#[derive(Debug)]
+pub enum PhotonError {
+    UnknownNode(NodeId),
+    /// `make-node` with the id of a node that still exists.
+    DuplicateNode(NodeId),
+    UnknownType(String),
+    BadAttributeValue { node: NodeId, attribute: String, value: Value, reason: String },
+    Protocol(String),
+    /// A children update would break the tree, or the Dom is inconsistent after a batch (checked in debug builds).
+    Integrity(String),
+    /// Applying a batch panicked; the rest of the batch was not applied.
+    Panic { message: String, backtrace: String },
+}
+
+impl PhotonError {
+    /// Stable identifier of the error kind, reported to the server.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PhotonError::UnknownNode(_) => "unknown-node",
+            PhotonError::DuplicateNode(_) => "duplicate-node",
+            PhotonError::UnknownType(_) => "unknown-type",
+            PhotonError::BadAttributeValue { .. } => "bad-attribute-value",
+            PhotonError::Protocol(_) => "protocol",
+            PhotonError::Integrity(_) => "integrity",
+            PhotonError::Panic { .. } => "panic",
+        }
+    }
+
+    pub fn node(&self) -> Option<NodeId> {
+        match self {
+            PhotonError::UnknownNode(node) => Some(*node),
+            PhotonError::DuplicateNode(node) => Some(*node),
+            PhotonError::BadAttributeValue { node, .. } => Some(*node),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for PhotonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PhotonError::UnknownNode(node) => write!(f, "no node with id {}", node),
+            PhotonError::DuplicateNode(node) => write!(f, "node {} already exists", node),
+            PhotonError::UnknownType(node_type) => write!(f, "unknown node type {:?}", node_type),
+            PhotonError::BadAttributeValue { node, attribute, value, reason } =>
+                write!(f, "bad value {} for attribute {:?} of node {}: {}", value, attribute, node, reason),
+            PhotonError::Protocol(message) => write!(f, "protocol error: {}", message),
+            PhotonError::Integrity(message) => write!(f, "dom integrity violation: {}", message),
+            PhotonError::Panic { message, .. } => write!(f, "panic while applying updates: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for PhotonError {}
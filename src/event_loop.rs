@@ -16,9 +16,10 @@ use webrender::hal::Instance;
 
 use crate::text::*;
 use crate::dom as dom;
+use crate::connection::Transport;
+use crate::handshake::HandshakeError;
 use std::time::{Duration};
 use std::env;
-use std::net::{ToSocketAddrs, Ipv4Addr};
 use serde::Deserialize;
 use crossbeam::crossbeam_channel::{Sender, Receiver};
 use winit::{Window, EventsLoop, MouseScrollDelta, Event, ControlFlow, ElementState, MouseButton, WindowEvent};
@@ -82,7 +83,8 @@ fn framebuffer_size(window: &Window) -> DeviceIntSize {
 
 enum UserEvent {
     Scroll { cursor_position: WorldPoint, delta: MouseScrollDelta },
-    Repaint
+    Repaint,
+    Shutdown,
 }
 
 #[derive(Clone)]
@@ -107,6 +109,16 @@ impl EventLoopNotifier {
     }
 }
 
+/// Stops the event loop from any thread, as if the window was closed.
+#[derive(Clone)]
+pub struct EventLoopStopper(EventLoopNotifier);
+
+impl EventLoopStopper {
+    pub fn stop(&self) {
+        self.0.send(UserEvent::Shutdown)
+    }
+}
+
 impl webrender::api::RenderNotifier for EventLoopNotifier {
     fn clone(&self) -> Box<RenderNotifier> {
         Box::new(EventLoopNotifier {
@@ -131,7 +143,11 @@ impl webrender::api::RenderNotifier for EventLoopNotifier {
     }
 }
 
-pub fn run_event_loop<A: ToSocketAddrs>(render_server_addr: A) {
+/// `on_connected` lets an embedder hook into the controller before any input is processed.
+/// Without a `transport` the window shows only updates applied through the controller.
+/// Returns once the window is closed or stopped, with the server connection and renderer torn down,
+/// or right away if the server can't be reached.
+pub fn run_event_loop<T: Transport, F: FnOnce(&mut dom::Controller, EventLoopStopper)>(transport: Option<T>, on_connected: F) -> Result<(), HandshakeError> {
     let mut events_loop = EventsLoop::new();
     let notifier = EventLoopNotifier::new(&events_loop);
 
@@ -149,7 +165,14 @@ pub fn run_event_loop<A: ToSocketAddrs>(render_server_addr: A) {
     txn.set_root_pipeline(pipeline_id);
     txn.generate_frame();
     api.send_transaction(document_id, txn);
-    let mut controller = dom::NoriaClient::spawn(render_server_addr, sender.clone(), pipeline_id, document_id, layout_size);
+    let mut controller = match dom::NoriaClient::spawn(transport, sender.clone(), pipeline_id, document_id, layout_size) {
+        Ok(controller) => controller,
+        Err(e) => {
+            renderer.deinit();
+            return Err(e);
+        }
+    };
+    on_connected(&mut controller, EventLoopStopper(Clone::clone(&notifier)));
 
     let mut cursor_position = WorldPoint::zero();
 
@@ -165,6 +188,9 @@ pub fn run_event_loop<A: ToSocketAddrs>(render_server_addr: A) {
                         renderer.render(framebuffer_size).unwrap();
                         renderer.flush_pipeline_info();
                     },
+                    UserEvent::Shutdown => {
+                        return ControlFlow::Break;
+                    }
                 }
             },
             Event::WindowEvent { event: window_event, .. } => match window_event {
@@ -234,6 +260,7 @@ pub fn run_event_loop<A: ToSocketAddrs>(render_server_addr: A) {
         return ControlFlow::Continue;
     });
 
-
+    controller.shutdown();
     renderer.deinit();
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,186 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+
+use crate::codec::{Frame, FrameReader, FrameWriter};
+use crate::dom::{NodeTypeSpec, NODE_TYPES};
+use crate::transport::Encoding;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+pub const CALLBACK_KINDS: &[&str] = &["noria-handler-sync", "noria-handler-async", "-noria-handler"];
+
+/// Lets servers know which `update-type`s they may send, e.g. whether `move` can replace remove and add.
+pub const UPDATE_TYPES: &[&str] = &["make-node", "destroy", "add", "remove", "move", "set-children", "set-attr", "set-callback"];
+
+#[derive(Serialize)]
+struct ClientHello<'a> {
+    kind: &'a str,
+    #[serde(rename = "protocol-version")]
+    protocol_version: u32,
+    #[serde(rename = "min-protocol-version")]
+    min_protocol_version: u32,
+    #[serde(rename = "node-types")]
+    node_types: &'a [NodeTypeSpec],
+    #[serde(rename = "callback-kinds")]
+    callback_kinds: &'a [&'a str],
+    #[serde(rename = "update-types")]
+    update_types: &'a [&'a str],
+    encodings: &'a [Encoding],
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ServerHello {
+    Rejected { error: String },
+    Accepted {
+        version: u32,
+        encoding: String,
+        #[serde(default)]
+        options: serde_json::Map<String, Value>,
+    },
+}
+
+/// What both sides agreed on; lives as long as the connection.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub version: u32,
+    pub encoding: Encoding,
+    pub options: serde_json::Map<String, Value>,
+}
+
+#[derive(Debug)]
+pub enum HandshakeError {
+    Io(io::Error),
+    Closed,
+    Malformed(serde_json::Error),
+    Rejected(String),
+    UnsupportedVersion(u32),
+    UnsupportedEncoding(String),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandshakeError::Io(e) => write!(f, "i/o error during handshake: {}", e),
+            HandshakeError::Closed => write!(f, "server closed connection before replying to handshake"),
+            HandshakeError::Malformed(e) => write!(f, "malformed handshake reply: {}", e),
+            HandshakeError::Rejected(reason) => write!(f, "server rejected handshake: {}", reason),
+            HandshakeError::UnsupportedVersion(version) =>
+                write!(f, "server chose protocol version {}, photon supports {}..={}", version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION),
+            HandshakeError::UnsupportedEncoding(encoding) =>
+                write!(f, "server chose encoding {:?}, photon supports {:?}", encoding, Encoding::ALL),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl From<io::Error> for HandshakeError {
+    fn from(e: io::Error) -> Self {
+        HandshakeError::Io(e)
+    }
+}
+
+pub fn handshake<R: Read, W: Write>(reader: &mut FrameReader<R>, writer: &mut FrameWriter<W>) -> Result<Session, HandshakeError> {
+    let hello = ClientHello {
+        kind: "webrender",
+        protocol_version: PROTOCOL_VERSION,
+        min_protocol_version: MIN_PROTOCOL_VERSION,
+        node_types: NODE_TYPES,
+        callback_kinds: CALLBACK_KINDS,
+        update_types: UPDATE_TYPES,
+        encodings: Encoding::ALL,
+    };
+    writer.write_frame(&Frame::json(&hello))?;
+    let reply = reader.read_frame()?.ok_or(HandshakeError::Closed)?;
+    match serde_json::from_slice::<ServerHello>(reply.payload()).map_err(HandshakeError::Malformed)? {
+        ServerHello::Rejected { error } => Err(HandshakeError::Rejected(error)),
+        ServerHello::Accepted { version, encoding, options } => {
+            if version < MIN_PROTOCOL_VERSION || version > PROTOCOL_VERSION {
+                return Err(HandshakeError::UnsupportedVersion(version));
+            }
+            let encoding = Encoding::from_name(&encoding).ok_or(HandshakeError::UnsupportedEncoding(encoding))?;
+            Ok(Session { version, encoding, options })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn reply(json: &str) -> FrameReader<Cursor<Vec<u8>>> {
+        let mut writer = FrameWriter::new(Vec::new());
+        writer.write_frame(&Frame(json.as_bytes().to_vec())).unwrap();
+        FrameReader::new(Cursor::new(writer.get_ref().clone()))
+    }
+
+    fn run(json: &str) -> (Result<Session, HandshakeError>, Value) {
+        let mut reader = reply(json);
+        let mut writer = FrameWriter::new(Vec::new());
+        let result = handshake(&mut reader, &mut writer);
+        let sent = FrameReader::new(Cursor::new(writer.get_ref().clone())).read_frame().unwrap().unwrap();
+        (result, serde_json::from_slice(sent.payload()).unwrap())
+    }
+
+    #[test]
+    fn test_hello_announces_capabilities() {
+        let (_, hello) = run(r#"{"version": 1, "encoding": "json"}"#);
+        assert_eq!(hello["kind"], "webrender");
+        assert_eq!(hello["protocol-version"], PROTOCOL_VERSION);
+        assert_eq!(hello["encodings"], serde_json::json!(["json", "msgpack"]));
+        assert!(hello["update-types"].as_array().unwrap().iter().any(|t| t == "move"));
+        let node_types = hello["node-types"].as_array().unwrap();
+        assert!(node_types.iter().any(|t| t["name"] == "div"));
+    }
+
+    #[test]
+    fn test_accepted() {
+        let (result, _) = run(r#"{"version": 1, "encoding": "json", "options": {"foo": 1}}"#);
+        let session = result.unwrap();
+        assert_eq!(session.version, 1);
+        assert_eq!(session.encoding, Encoding::Json);
+        assert_eq!(session.options["foo"], 1);
+    }
+
+    #[test]
+    fn test_binary_encoding() {
+        let (result, _) = run(r#"{"version": 1, "encoding": "msgpack"}"#);
+        assert_eq!(result.unwrap().encoding, Encoding::MessagePack);
+    }
+
+    #[test]
+    fn test_mismatches() {
+        match run(r#"{"version": 42, "encoding": "json"}"#).0 {
+            Err(HandshakeError::UnsupportedVersion(42)) => (),
+            other => panic!("{:?}", other),
+        }
+        match run(r#"{"version": 1, "encoding": "xml"}"#).0 {
+            Err(HandshakeError::UnsupportedEncoding(_)) => (),
+            other => panic!("{:?}", other),
+        }
+        match run(r#"{"error": "too old"}"#).0 {
+            Err(HandshakeError::Rejected(reason)) => assert_eq!(reason, "too old"),
+            other => panic!("{:?}", other),
+        }
+        match run(r#"{"kind": "hello"}"#).0 {
+            Err(HandshakeError::Malformed(_)) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_closed() {
+        let mut reader = FrameReader::new(Cursor::new(Vec::new()));
+        let mut writer = FrameWriter::new(Vec::new());
+        match handshake(&mut reader, &mut writer) {
+            Err(HandshakeError::Closed) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+}
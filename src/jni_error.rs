@@ -0,0 +1,73 @@
+use std::any::Any;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+
+use backtrace::Backtrace;
+use jni::JNIEnv;
+use jni::objects::{JThrowable, JValue};
+
+use crate::panics::{self, CaughtPanic};
+
+const EXCEPTION_CLASS: &str = "photon/PhotonException";
+
+/// Failure of a JNI entry point, rethrown on the Java side as `photon.PhotonException`.
+pub struct JniError {
+    message: String,
+    backtrace: String,
+}
+
+impl JniError {
+    pub fn new<S: Into<String>>(message: S) -> Self {
+        JniError { message: message.into(), backtrace: format!("{:?}", Backtrace::new()) }
+    }
+
+    /// Describes a panic caught on another thread, e.g. by `JoinHandle::join`; its backtrace is lost.
+    pub fn from_panic(context: &str, payload: Box<dyn Any + Send>) -> Self {
+        JniError::from_caught_panic(context, CaughtPanic::from_payload(&*payload))
+    }
+
+    /// Describes a panic with the backtrace captured where it happened, if any.
+    pub fn from_caught_panic(context: &str, panic: CaughtPanic) -> Self {
+        JniError { message: format!("{}: {}", context, panic.message), backtrace: panic.backtrace }
+    }
+}
+
+impl<E: std::error::Error> From<E> for JniError {
+    fn from(e: E) -> Self {
+        JniError::new(e.to_string())
+    }
+}
+
+impl fmt::Debug for JniError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}\n{}", self.message, self.backtrace)
+    }
+}
+
+/// Runs the body of a JNI entry point. Errors and panics are thrown as `photon.PhotonException`,
+/// in which case `default` is returned to the JVM.
+pub fn guard<T, F>(env: &JNIEnv, default: T, body: F) -> T where F: FnOnce() -> Result<T, JniError> {
+    let error = match panics::catch(AssertUnwindSafe(body)) {
+        Ok(Ok(result)) => return result,
+        Ok(Err(error)) => error,
+        Err(panic) => JniError { message: panic.message, backtrace: panic.backtrace },
+    };
+    throw(env, &error);
+    default
+}
+
+fn throw(env: &JNIEnv, error: &JniError) {
+    // a failed JNI call may have left its own exception pending, which says more
+    if env.exception_check().unwrap_or(false) {
+        return;
+    }
+    let thrown = env.new_string(&error.message)
+        .and_then(|message| Ok((message, env.new_string(&error.backtrace)?)))
+        .and_then(|(message, backtrace)| env.new_object(EXCEPTION_CLASS,
+                                                        "(Ljava/lang/String;Ljava/lang/String;)V",
+                                                        &[JValue::Object(message.into()), JValue::Object(backtrace.into())]))
+        .and_then(|exception| env.throw(JThrowable::from(exception)));
+    if let Err(e) = thrown {
+        log::error!("Can't throw {} for {:?}: {}", EXCEPTION_CLASS, error, e);
+    }
+}
@@ -1,32 +1,316 @@
-use jni::JNIEnv;
-use jni::objects::{JClass, JString};
-use jni::sys::{jfloat, jint};
+use jni::{JNIEnv, JavaVM};
+use jni::objects::{GlobalRef, JByteBuffer, JClass, JObject, JString, JValue};
+use jni::sys::{jboolean, jfloat, jint, jlong, jobject, JNI_FALSE, JNI_TRUE};
+use lazy_static::lazy_static;
+use fxhash::FxHashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::JoinHandle;
+
+use crate::callbacks::{CallbackMessage, CallbackSink};
+use crate::jni_error::{guard, JniError};
 
 mod text;
 mod dom;
 mod transport;
+mod codec;
+mod handshake;
+mod connection;
+mod error;
+mod recorder;
+mod callbacks;
+mod render_backend;
+mod display_list;
+mod color;
 mod text_layout;
 mod event_loop;
+mod panics;
+mod jni_error;
+
+lazy_static! {
+    /// Instances started by `start`, until `waitForExit` releases them.
+    static ref INSTANCES: Mutex<FxHashMap<jlong, Arc<Instance>>> = Mutex::new(FxHashMap::default());
+    /// Listener registered with `setCallbackListener`, picked up by the next `start`.
+    static ref CALLBACK_LISTENER: Mutex<Option<Arc<JniCallbackSink>>> = Mutex::new(None);
+}
+
+static NEXT_HANDLE: AtomicUsize = AtomicUsize::new(1);
+
+/// Photon window running its event loop on a thread of its own.
+struct Instance {
+    state: Mutex<InstanceState>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    running: AtomicBool,
+    /// Why the event loop couldn't start, e.g. the server rejected the handshake.
+    error: Mutex<Option<String>>,
+}
+
+/// Filled in once the instance is connected to the server.
+#[derive(Default)]
+struct InstanceState {
+    updates: Option<dom::UpdatesSender>,
+    stopper: Option<event_loop::EventLoopStopper>,
+    /// `stop` was called before there was anything to stop.
+    stop_requested: bool,
+}
+
+/// Marks the instance as no longer running when the event loop thread ends, even by a panic.
+struct ExitGuard(Arc<Instance>);
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        *self.0.state.lock().unwrap_or_else(PoisonError::into_inner) = InstanceState::default();
+        self.0.running.store(false, Ordering::SeqCst);
+    }
+}
+
+fn instance(handle: jlong) -> Option<Arc<Instance>> {
+    INSTANCES.lock().unwrap().get(&handle).cloned()
+}
+
+/// Calls `photon.CallbackListener.onCallback` on a Java object.
+struct JniCallbackSink {
+    jvm: JavaVM,
+    listener: GlobalRef,
+}
+
+impl JniCallbackSink {
+    fn call_listener(&self, env: &JNIEnv, msg: &CallbackMessage) -> jni::errors::Result<()> {
+        let key = env.new_string(msg.key)?;
+        let arguments = env.new_float_array(msg.arguments.len() as i32)?;
+        env.set_float_array_region(arguments, 0, &msg.arguments)?;
+        env.call_method(self.listener.as_obj(),
+                        "onCallback",
+                        "(JLjava/lang/String;J[F)V",
+                        &[JValue::Long(msg.node as i64),
+                          JValue::Object(key.into()),
+                          JValue::Long(msg.log_id as i64),
+                          JValue::Object(JObject::from(arguments))])?;
+        env.delete_local_ref(key.into())?;
+        env.delete_local_ref(JObject::from(arguments))
+    }
+}
+
+impl CallbackSink for JniCallbackSink {
+    fn send_callback(&self, msg: &CallbackMessage) {
+        // Event loop thread may be a native thread. Attach it for good:
+        // attaching and detaching on every mouse event is too expensive.
+        // jni detaches it when the thread exits.
+        let env = match self.jvm.attach_current_thread_permanently() {
+            Ok(env) => env,
+            Err(e) => {
+                log::error!("Can't attach thread to JVM: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = self.call_listener(&env, msg) {
+            log::error!("Can't deliver {} callback to JVM: {}", msg.key, e);
+            if env.exception_check().unwrap_or(false) {
+                let _ = env.exception_describe();
+                let _ = env.exception_clear();
+            }
+        }
+    }
+}
 
 #[no_mangle]
 #[allow(non_snake_case)]
-pub extern "system" fn Java_photon_PhotonApi_run(env: JNIEnv,
-                                                 class: JClass,
-                                                 port: jint) {
-    event_loop::run_event_loop(("localhost", port as u16));
+pub extern "system" fn Java_photon_PhotonApi_start(env: JNIEnv,
+                                                   class: JClass,
+                                                   port: jint) -> jlong {
+    guard(&env, 0, || {
+        let transport = connection::TcpTransport::new(("localhost", port as u16))?;
+        start(Some(transport))
+    })
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_photon_PhotonApi_startEmbedded(env: JNIEnv,
+                                                           class: JClass) -> jlong {
+    guard(&env, 0, || start(None::<connection::TcpTransport>))
+}
+
+/// Opens a window on an event loop thread of its own and returns its handle.
+fn start<T: connection::Transport>(transport: Option<T>) -> Result<jlong, JniError> {
+    let listener = CALLBACK_LISTENER.lock().unwrap().clone();
+    let instance = Arc::new(Instance {
+        state: Mutex::new(InstanceState::default()),
+        thread: Mutex::new(None),
+        running: AtomicBool::new(true),
+        error: Mutex::new(None),
+    });
+    let thread_instance = instance.clone();
+    let thread = std::thread::Builder::new()
+        .name("Event loop".to_owned())
+        .spawn(move || {
+            let _exit = ExitGuard(thread_instance.clone());
+            let result = event_loop::run_event_loop(transport, |controller, stopper| {
+                if let Some(listener) = listener {
+                    controller.set_callback_sink(listener);
+                }
+                let mut state = thread_instance.state.lock().unwrap();
+                if state.stop_requested {
+                    stopper.stop();
+                }
+                state.updates = Some(controller.updates());
+                state.stopper = Some(stopper);
+            });
+            if let Err(e) = result {
+                log::error!("Can't connect to Noria: {}", e);
+                *thread_instance.error.lock().unwrap() = Some(format!("Can't connect to Noria: {}", e));
+            }
+        })?;
+    *instance.thread.lock().unwrap() = Some(thread);
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::SeqCst) as jlong;
+    INSTANCES.lock().unwrap().insert(handle, instance);
+    Ok(handle)
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_photon_PhotonApi_stop(env: JNIEnv,
+                                                  class: JClass,
+                                                  handle: jlong) {
+    guard(&env, (), || {
+        if let Some(instance) = instance(handle) {
+            let mut state = instance.state.lock().unwrap();
+            match &state.stopper {
+                Some(stopper) => stopper.stop(),
+                None => state.stop_requested = true,
+            }
+        }
+        Ok(())
+    })
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_photon_PhotonApi_isRunning(env: JNIEnv,
+                                                       class: JClass,
+                                                       handle: jlong) -> jboolean {
+    guard(&env, JNI_FALSE, || {
+        Ok(match instance(handle) {
+            Some(ref instance) if instance.running.load(Ordering::SeqCst) => JNI_TRUE,
+            _ => JNI_FALSE,
+        })
+    })
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_photon_PhotonApi_waitForExit(env: JNIEnv,
+                                                         class: JClass,
+                                                         handle: jlong) {
+    guard(&env, (), || {
+        if let Some(instance) = instance(handle) {
+            // held while joining so concurrent callers also wait for the exit
+            let mut thread = instance.thread.lock().unwrap();
+            let joined = match thread.take() {
+                Some(thread) => thread.join(),
+                None => Ok(()),
+            };
+            INSTANCES.lock().unwrap().remove(&handle);
+            joined.map_err(|payload| JniError::from_panic("event loop thread panicked", payload))?;
+            if let Some(error) = instance.error.lock().unwrap().clone() {
+                return Err(JniError::new(error));
+            }
+        }
+        Ok(())
+    })
+}
+
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_photon_PhotonApi_setCallbackListener(env: JNIEnv,
+                                                                 class: JClass,
+                                                                 listener: JObject) {
+    guard(&env, (), || {
+        let sink = if listener.is_null() {
+            None
+        } else {
+            Some(Arc::new(JniCallbackSink {
+                jvm: env.get_java_vm()?,
+                listener: env.new_global_ref(listener)?,
+            }))
+        };
+        *CALLBACK_LISTENER.lock().unwrap() = sink;
+        Ok(())
+    })
 }
 
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "system" fn Java_photon_PhotonApi_applyUpdates(env: JNIEnv,
                                                           class: JClass,
+                                                          handle: jlong,
                                                           updates: JString) {
+    guard(&env, (), || {
+        let updates: String = env.get_string(updates)?.into();
+        apply_updates(handle, updates.as_bytes())
+    })
+}
 
+#[no_mangle]
+#[allow(non_snake_case)]
+pub extern "system" fn Java_photon_PhotonApi_applyUpdatesBuffer(env: JNIEnv,
+                                                                class: JClass,
+                                                                handle: jlong,
+                                                                updates: JByteBuffer,
+                                                                offset: jint,
+                                                                length: jint) {
+    guard(&env, (), || {
+        // not copied: the batch is decoded straight from the buffer memory, which Java keeps alive for the call
+        let buffer = env.get_direct_buffer_address(updates)?;
+        let (offset, length) = (offset as usize, length as usize);
+        if offset.checked_add(length).map_or(true, |end| end > buffer.len()) {
+            return Err(JniError::new(format!("range {}+{} is out of buffer capacity {}", offset, length, buffer.len())));
+        }
+        apply_updates(handle, &buffer[offset..offset + length])
+    })
+}
+
+fn apply_updates(handle: jlong, message: &[u8]) -> Result<(), JniError> {
+    let instance = instance(handle).ok_or_else(|| JniError::new(format!("photon instance {} is not running", handle)))?;
+    let sender = instance.state.lock().unwrap().updates.clone();
+    let sender = match (sender, instance.error.lock().unwrap().as_ref()) {
+        (Some(sender), _) => sender,
+        (None, Some(error)) => return Err(JniError::new(error.clone())),
+        (None, None) => return Err(JniError::new(format!("photon instance {} is not running", handle))),
+    };
+    let errors = sender.apply(message, transport::Encoding::Json);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    if let Some(error::PhotonError::Panic { message, backtrace }) = errors.last() {
+        let panic = panics::CaughtPanic { message: message.clone(), backtrace: backtrace.clone() };
+        return Err(JniError::from_caught_panic("Can't apply updates", panic));
+    }
+    let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    Err(JniError::new(format!("Can't apply {} update(s): {}", errors.len(), messages.join("; "))))
 }
 
 #[no_mangle]
 #[allow(non_snake_case)]
 pub extern "system" fn Java_photon_PhotonApi_measureText(env: JNIEnv,
-                                                         class: JClass) -> jfloat {
-    0.0
-}
\ No newline at end of file
+                                                         class: JClass,
+                                                         text: JString,
+                                                         font: JString,
+                                                         size: jfloat) -> jobject {
+    guard(&env, JObject::null().into_inner(), || {
+        let text: String = env.get_string(text)?.into();
+        let font: Option<String> = if font.is_null() {
+            None
+        } else {
+            Some(env.get_string(font)?.into())
+        };
+        let metrics = text::measure_text(font.as_ref().map(String::as_str), &text, size)?;
+        let metrics = env.new_object("photon/TextMetrics",
+                                     "(FFFF)V",
+                                     &[JValue::Float(metrics.width),
+                                       JValue::Float(metrics.height),
+                                       JValue::Float(metrics.ascent),
+                                       JValue::Float(metrics.descent)])?;
+        Ok(metrics.into_inner())
+    })
+}
@@ -6,28 +6,68 @@ use std::io::BufReader;
 
 use serde::{Deserialize};
 
+use crate::connection::Transport;
+
 mod text;
 mod dom;
 mod transport;
+mod codec;
+mod handshake;
+mod connection;
+mod error;
+mod recorder;
+mod callbacks;
+mod render_backend;
+mod display_list;
+mod color;
 mod text_layout;
 mod event_loop;
+mod panics;
 
 #[derive(Deserialize)]
 struct PortFileContent {
     #[serde(rename = "httpPort")]
     http_port: u16,
     #[serde(rename = "tcpPort")]
-    tcp_port: u16
+    tcp_port: u16,
+    #[serde(rename = "unixSocket", default)]
+    unix_socket: Option<String>,
+}
+
+fn server_transport(port_file: &str) -> std::io::Result<Box<dyn Transport>> {
+    let f = File::open(port_file).expect("No port file");
+    let content: PortFileContent = serde_json::from_reader(BufReader::new(f)).unwrap();
+    match content.unix_socket {
+        #[cfg(unix)]
+        Some(path) => Ok(Box::new(connection::UnixTransport::new(path))),
+        _ => Ok(Box::new(connection::TcpTransport::new((Ipv4Addr::new(127, 0, 0, 1), content.tcp_port))?)),
+    }
 }
 
+// photon <port-file> [--record <session-file>]
+// photon --replay <session-file> [--realtime]
 fn main() -> std::io::Result<()> {
     env_logger::init();
     let args: Vec<String> = env::args().collect();
-    if args.len() >= 2 {
-        let port_file = &args[1];
-        let f = File::open(port_file).expect("No port file");
-        let content: PortFileContent = serde_json::from_reader(BufReader::new(f)).unwrap();
-        event_loop::run_event_loop((Ipv4Addr::new(127, 0, 0, 1), content.tcp_port));
+    let flag_value = |flag: &str| args.iter().position(|a| a == flag).and_then(|i| args.get(i + 1));
+    let result = if let Some(session_file) = flag_value("--replay") {
+        let realtime = args.iter().any(|a| a == "--realtime");
+        event_loop::run_event_loop(Some(recorder::ReplayTransport::new(session_file, realtime)), |_, _| ())
+    } else if args.len() >= 2 {
+        let transport = server_transport(&args[1])?;
+        match flag_value("--record") {
+            Some(session_file) => {
+                let recorder = recorder::Recorder::create(session_file)?;
+                event_loop::run_event_loop(Some(recorder::RecordingTransport::new(transport, recorder)), |_, _| ())
+            }
+            None => event_loop::run_event_loop(Some(transport), |_, _| ()),
+        }
+    } else {
+        Ok(())
+    };
+    if let Err(e) = result {
+        log::error!("Can't connect to Noria: {}", e);
+        std::process::exit(1);
     }
     return Ok(());
 }
\ No newline at end of file
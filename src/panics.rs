@@ -0,0 +1,68 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, UnwindSafe};
+use std::sync::Once;
+
+use backtrace::Backtrace;
+
+/// Panic caught by `catch`, with the backtrace of where it happened when the hook could capture it.
+#[derive(Debug)]
+pub struct CaughtPanic {
+    pub message: String,
+    pub backtrace: String,
+}
+
+impl CaughtPanic {
+    /// Describes a panic from its payload alone, e.g. one returned by `JoinHandle::join`; there is no backtrace.
+    pub fn from_payload(payload: &(dyn Any + Send)) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message
+        } else {
+            "Box<Any>"
+        };
+        CaughtPanic { message: message.to_string(), backtrace: String::new() }
+    }
+}
+
+thread_local! {
+    /// Message and backtrace of the last panic on this thread, captured by the panic hook.
+    static LAST_PANIC: RefCell<Option<CaughtPanic>> = RefCell::new(None);
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+/// The backtrace is only available while the panic unwinds, so it is saved from a hook.
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let caught = CaughtPanic { message: info.to_string(), backtrace: format!("{:?}", Backtrace::new()) };
+            LAST_PANIC.with(|last| *last.borrow_mut() = Some(caught));
+            default_hook(info);
+        }));
+    });
+}
+
+/// Runs `body`, turning a panic into an error instead of unwinding further.
+pub fn catch<T, F>(body: F) -> Result<T, CaughtPanic> where F: FnOnce() -> T + UnwindSafe {
+    install_panic_hook();
+    panic::catch_unwind(body).map_err(|payload| {
+        LAST_PANIC.with(|last| last.borrow_mut().take())
+            .unwrap_or_else(|| CaughtPanic::from_payload(&*payload))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catch() {
+        assert_eq!(catch(|| 42).unwrap(), 42);
+        let caught = catch(|| -> u32 { panic!("broken {}", "dom") }).unwrap_err();
+        assert!(caught.message.contains("broken dom"), "{}", caught.message);
+        assert!(!caught.backtrace.is_empty());
+    }
+}
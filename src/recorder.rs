@@ -0,0 +1,367 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt, BigEndian};
+
+use crate::codec::{Frame, FrameReader, FrameWriter};
+use crate::connection::{ReadHalf, Transport, WriteHalf, WriteStream};
+
+// Session file is a sequence of records:
+// direction (u8), unix timestamp in microseconds (u64), then the frame exactly as it was on the wire.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Incoming = 0,
+    Outgoing = 1,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub direction: Direction,
+    pub ts_micros: u64,
+    pub frame: Frame,
+}
+
+fn now_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64
+}
+
+/// Appends every frame passing through a connection to a session file.
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<BufWriter<File>>>);
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Recorder(Arc::new(Mutex::new(BufWriter::new(File::create(path)?)))))
+    }
+
+    /// Flushes every record, so a session is complete on disk even if photon crashes or is killed.
+    pub fn record(&self, direction: Direction, frame: &Frame) {
+        let mut file = self.0.lock().unwrap();
+        if let Err(e) = write_record(&mut *file, direction, now_micros(), frame).and_then(|_| file.flush()) {
+            log::error!("Can't record frame: {}", e);
+        }
+    }
+}
+
+fn write_record<W: Write>(writer: &mut W, direction: Direction, ts_micros: u64, frame: &Frame) -> io::Result<()> {
+    writer.write_u8(direction as u8)?;
+    writer.write_u64::<BigEndian>(ts_micros)?;
+    FrameWriter::new(writer).write_frame(frame)
+}
+
+pub fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<Record>> {
+    let direction = match reader.read_u8() {
+        Ok(0) => Direction::Incoming,
+        Ok(1) => Direction::Outgoing,
+        Ok(d) => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown record direction {}", d))),
+        Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let ts_micros = reader.read_u64::<BigEndian>()?;
+    let frame = FrameReader::new(reader).read_frame()?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "truncated record"))?;
+    Ok(Some(Record { direction, ts_micros, frame }))
+}
+
+/// Splits the byte stream flowing in one direction back into frames and records them.
+struct FrameTap {
+    direction: Direction,
+    recorder: Recorder,
+    buf: Vec<u8>,
+}
+
+impl FrameTap {
+    fn feed(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+        while self.buf.len() >= 4 {
+            let len = BigEndian::read_u32(&self.buf[..4]) as usize;
+            if self.buf.len() < 4 + len {
+                break;
+            }
+            let payload = self.buf[4..4 + len].to_vec();
+            self.buf.drain(..4 + len);
+            self.recorder.record(self.direction, &Frame(payload));
+        }
+    }
+}
+
+struct RecordingReader {
+    inner: ReadHalf,
+    tap: FrameTap,
+}
+
+impl Read for RecordingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.tap.feed(&buf[..n]);
+        Ok(n)
+    }
+}
+
+struct RecordingWriter {
+    inner: WriteHalf,
+    tap: FrameTap,
+}
+
+impl Write for RecordingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.tap.feed(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl WriteStream for RecordingWriter {
+    fn shutdown(&self) -> io::Result<()> {
+        self.inner.shutdown()
+    }
+
+    fn shutdown_handle(&self) -> io::Result<WriteHalf> {
+        self.inner.shutdown_handle()
+    }
+}
+
+/// Records everything sent and received over the wrapped transport, including handshakes after reconnects.
+pub struct RecordingTransport<T: Transport> {
+    inner: T,
+    recorder: Recorder,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    pub fn new(inner: T, recorder: Recorder) -> Self {
+        RecordingTransport { inner, recorder }
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn connect(&self) -> io::Result<(ReadHalf, WriteHalf)> {
+        let (read_half, write_half) = self.inner.connect()?;
+        let tap = |direction| FrameTap { direction, recorder: self.recorder.clone(), buf: Vec::new() };
+        let read_half: ReadHalf = Box::new(RecordingReader { inner: read_half, tap: tap(Direction::Incoming) });
+        let write_half: WriteHalf = Box::new(RecordingWriter { inner: write_half, tap: tap(Direction::Outgoing) });
+        Ok((read_half, write_half))
+    }
+
+    fn can_reconnect(&self) -> bool {
+        self.inner.can_reconnect()
+    }
+}
+
+/// Set when the replayed connection is shut down, which ends the replay even while it waits for the next record.
+#[derive(Default)]
+struct ReplayStop {
+    stopped: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl ReplayStop {
+    fn stop(&self) {
+        *self.stopped.lock().unwrap() = true;
+        self.changed.notify_all();
+    }
+
+    fn is_stopped(&self) -> bool {
+        *self.stopped.lock().unwrap()
+    }
+
+    /// Waits until `deadline`; returns false if the replay is stopped first.
+    fn sleep_until(&self, deadline: Instant) -> bool {
+        let mut stopped = self.stopped.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            if *stopped || now >= deadline {
+                return !*stopped;
+            }
+            stopped = self.changed.wait_timeout(stopped, deadline - now).unwrap().0;
+        }
+    }
+}
+
+/// Write half of a replayed connection: drops everything photon sends, and its shutdown ends the replay.
+struct ReplayWriter(Arc<ReplayStop>);
+
+impl Write for ReplayWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WriteStream for ReplayWriter {
+    fn shutdown(&self) -> io::Result<()> {
+        self.0.stop();
+        Ok(())
+    }
+
+    fn shutdown_handle(&self) -> io::Result<WriteHalf> {
+        Ok(Box::new(ReplayWriter(self.0.clone())))
+    }
+}
+
+/// Plays incoming frames of a recorded session back as if a server sent them; everything photon sends is dropped.
+pub struct ReplayTransport {
+    path: PathBuf,
+    realtime: bool,
+    connected: AtomicBool,
+}
+
+impl ReplayTransport {
+    pub fn new<P: Into<PathBuf>>(path: P, realtime: bool) -> Self {
+        ReplayTransport { path: path.into(), realtime, connected: AtomicBool::new(false) }
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn connect(&self) -> io::Result<(ReadHalf, WriteHalf)> {
+        if self.connected.swap(true, Ordering::SeqCst) {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "replay is over"));
+        }
+        let stop = Arc::new(ReplayStop::default());
+        let reader = ReplayReader {
+            records: BufReader::new(File::open(&self.path)?),
+            realtime: self.realtime,
+            clock: None,
+            pending: io::Cursor::new(Vec::new()),
+            stop: stop.clone(),
+        };
+        let read_half: ReadHalf = Box::new(reader);
+        Ok((read_half, Box::new(ReplayWriter(stop))))
+    }
+
+    fn can_reconnect(&self) -> bool {
+        false
+    }
+}
+
+struct ReplayReader {
+    records: BufReader<File>,
+    realtime: bool,
+    /// First record timestamp and when it was replayed.
+    clock: Option<(u64, Instant)>,
+    pending: io::Cursor<Vec<u8>>,
+    stop: Arc<ReplayStop>,
+}
+
+impl ReplayReader {
+    fn next_incoming(&mut self) -> io::Result<Option<Frame>> {
+        while let Some(record) = read_record(&mut self.records)? {
+            if record.direction != Direction::Incoming {
+                continue;
+            }
+            if self.realtime {
+                let (start_ts, start) = *self.clock.get_or_insert((record.ts_micros, Instant::now()));
+                let due = start + Duration::from_micros(record.ts_micros.saturating_sub(start_ts));
+                if !self.stop.sleep_until(due) {
+                    return Ok(None);
+                }
+            }
+            return Ok(Some(record.frame));
+        }
+        Ok(None)
+    }
+}
+
+impl Read for ReplayReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stop.is_stopped() {
+            return Ok(0);
+        }
+        if self.pending.position() as usize == self.pending.get_ref().len() {
+            match self.next_incoming()? {
+                Some(frame) => {
+                    let mut bytes = Vec::new();
+                    FrameWriter::new(&mut bytes).write_frame(&frame)?;
+                    self.pending = io::Cursor::new(bytes);
+                }
+                None => return Ok(0),
+            }
+        }
+        self.pending.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use crate::connection::TcpTransport;
+
+    fn read_records(path: &Path) -> Vec<(Direction, Frame)> {
+        let mut file = BufReader::new(File::open(path).unwrap());
+        let mut records = Vec::new();
+        while let Some(record) = read_record(&mut file).unwrap() {
+            records.push((record.direction, record.frame));
+        }
+        records
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("photon-test-{}.session", std::process::id()));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = RecordingTransport::new(TcpTransport::new(listener.local_addr().unwrap()).unwrap(),
+                                                Recorder::create(&path).unwrap());
+        let (read_half, write_half) = transport.connect().unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let mut server_writer = FrameWriter::new(server.try_clone().unwrap());
+        server_writer.write_frame(&Frame(b"first".to_vec())).unwrap();
+        server_writer.write_frame(&Frame(b"second".to_vec())).unwrap();
+        let mut reader = FrameReader::new(read_half);
+        assert_eq!(reader.read_frame().unwrap(), Some(Frame(b"first".to_vec())));
+        FrameWriter::new(write_half).write_frame(&Frame(b"callback".to_vec())).unwrap();
+        assert_eq!(reader.read_frame().unwrap(), Some(Frame(b"second".to_vec())));
+
+        let expected = vec![
+            (Direction::Incoming, Frame(b"first".to_vec())),
+            (Direction::Outgoing, Frame(b"callback".to_vec())),
+            (Direction::Incoming, Frame(b"second".to_vec())),
+        ];
+        // the connection still holds the recorder, as it would if photon crashed now
+        assert_eq!(read_records(&path), expected);
+        drop(reader);
+        drop(transport);
+        assert_eq!(read_records(&path), expected);
+
+        let replay = ReplayTransport::new(&path, true);
+        let (read_half, _) = replay.connect().unwrap();
+        let mut reader = FrameReader::new(read_half);
+        assert_eq!(reader.read_frame().unwrap(), Some(Frame(b"first".to_vec())));
+        assert_eq!(reader.read_frame().unwrap(), Some(Frame(b"second".to_vec())));
+        assert_eq!(reader.read_frame().unwrap(), None);
+        assert!(replay.connect().is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_ends_realtime_replay() {
+        let path = std::env::temp_dir().join(format!("photon-test-{}-shutdown.session", std::process::id()));
+        let mut file = File::create(&path).unwrap();
+        write_record(&mut file, Direction::Incoming, 0, &Frame(b"first".to_vec())).unwrap();
+        write_record(&mut file, Direction::Incoming, 60_000_000, &Frame(b"a minute later".to_vec())).unwrap();
+        drop(file);
+
+        let (read_half, write_half) = ReplayTransport::new(&path, true).connect().unwrap();
+        let mut reader = FrameReader::new(read_half);
+        assert_eq!(reader.read_frame().unwrap(), Some(Frame(b"first".to_vec())));
+        let started = Instant::now();
+        let reading = std::thread::spawn(move || reader.read_frame().unwrap());
+        std::thread::sleep(Duration::from_millis(50));
+        write_half.shutdown_handle().unwrap().shutdown().unwrap();
+        assert_eq!(reading.join().unwrap(), None);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
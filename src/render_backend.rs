@@ -0,0 +1,77 @@
+use webrender::api::*;
+
+use crate::text::{FontsManager, LayoutedText};
+
+type NodeId = u64;
+
+/// What applying updates needs from the renderer, so the Dom can be updated and tested without one.
+pub trait RenderBackend {
+    fn layout_text(&mut self, text: &str) -> LayoutedText;
+    fn scroll_node(&mut self, node_id: NodeId, position: LayoutPoint);
+}
+
+/// Lays text out with the renderer's fonts and queues scrolling into the transaction of the batch.
+pub struct WebrenderBackend<'a> {
+    pub pipeline_id: PipelineId,
+    pub txn: &'a mut Transaction,
+    pub fonts_manager: &'a mut FontsManager,
+}
+
+impl<'a> RenderBackend for WebrenderBackend<'a> {
+    fn layout_text(&mut self, text: &str) -> LayoutedText {
+        self.fonts_manager.layout_simple_ascii(text)
+    }
+
+    fn scroll_node(&mut self, node_id: NodeId, position: LayoutPoint) {
+        self.txn.scroll_node_with_id(position, ExternalScrollId(node_id, self.pipeline_id), ScrollClamping::ToContentBounds);
+    }
+}
+
+/// Lays nothing out and drops scrolling, for validating updates without side effects.
+pub struct NullBackend;
+
+impl RenderBackend for NullBackend {
+    fn layout_text(&mut self, _text: &str) -> LayoutedText {
+        LayoutedText { glyphs: Vec::new(), size: LayoutSize::zero() }
+    }
+
+    fn scroll_node(&mut self, _node_id: NodeId, _position: LayoutPoint) {}
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendCall {
+    LayoutText(String),
+    ScrollNode(NodeId, LayoutPoint),
+}
+
+/// Remembers calls and lays text out in a monospace grid of `GLYPH_WIDTH` by `LINE_HEIGHT` cells.
+#[cfg(test)]
+#[derive(Default)]
+pub struct RecordingBackend {
+    pub calls: Vec<BackendCall>,
+}
+
+#[cfg(test)]
+impl RecordingBackend {
+    pub const GLYPH_WIDTH: f32 = 10.0;
+    pub const LINE_HEIGHT: f32 = 20.0;
+}
+
+#[cfg(test)]
+impl RenderBackend for RecordingBackend {
+    fn layout_text(&mut self, text: &str) -> LayoutedText {
+        self.calls.push(BackendCall::LayoutText(text.to_string()));
+        let glyphs = text.chars().enumerate()
+            .map(|(i, c)| GlyphInstance { index: c as u32, point: LayoutPoint::new(i as f32 * Self::GLYPH_WIDTH, Self::LINE_HEIGHT) })
+            .collect();
+        LayoutedText {
+            glyphs,
+            size: LayoutSize::new(text.chars().count() as f32 * Self::GLYPH_WIDTH, Self::LINE_HEIGHT),
+        }
+    }
+
+    fn scroll_node(&mut self, node_id: NodeId, position: LayoutPoint) {
+        self.calls.push(BackendCall::ScrollNode(node_id, position));
+    }
+}
@@ -1,36 +1,58 @@
 use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read};
+use std::cell::RefCell;
 
 use webrender::api::*;
 use fxhash::FxHashMap;
 use std::sync::Arc;
 use crate::text_layout;
 
-pub struct Font {
-    pub font_key: FontKey,
+/// Built in, so it's found whatever the working directory of the JVM or photon is.
+pub const DEFAULT_FONT: &[u8] = include_bytes!("../resources/Fira Code/ttf/FiraCode-Retina.ttf");
+
+/// Everything needed to shape and measure text; doesn't need a renderer.
+pub struct FontFace {
     pub font: font_kit::font::Font,
     pub hb_font: text_layout::HbFace
 }
 
+impl FontFace {
+    pub fn from_bytes(buffer: Vec<u8>) -> io::Result<FontFace> {
+        let font_index = 0; // 0 for single font file
+        let font = font_kit::handle::Handle::from_memory(Arc::new(buffer), font_index).load()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Can't load font: {:?}", e)))?;
+        let hb_font = text_layout::HbFace::new(&font);
+        Ok(FontFace {
+            font: font,
+            hb_font: hb_font
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<FontFace> {
+        let mut buffer = Vec::new();
+        File::open(path)?.read_to_end(&mut buffer)?;
+        FontFace::from_bytes(buffer)
+    }
+}
+
+pub struct Font {
+    pub font_key: FontKey,
+    pub face: FontFace
+}
+
 struct FontInstance {
     font_instance_key: FontInstanceKey,
     size: f32
 }
 
-pub fn load_font<P: AsRef<Path>>(api: &RenderApi, txn: &mut Transaction, path: P) -> Font {
+pub fn load_font(api: &RenderApi, txn: &mut Transaction, buffer: Vec<u8>) -> Font {
     let font_index = 0; // 0 for single font file
-    let mut file = File::open(path).unwrap();
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).unwrap();
     let font_key = api.generate_font_key();
     txn.add_raw_font(font_key, buffer.clone(), font_index);
-    let font = font_kit::handle::Handle::from_memory(Arc::new(buffer), font_index).load().unwrap();
-    let hb_font = text_layout::HbFace::new(&font);
     Font {
         font_key: font_key,
-        font: font,
-        hb_font: hb_font
+        face: FontFace::from_bytes(buffer).unwrap()
     }
 }
 
@@ -52,6 +74,50 @@ pub struct LayoutedText {
     pub size: LayoutSize,
 }
 
+/// Extents of a single line of text; `descent` is positive below the baseline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextMetrics {
+    pub width: f32,
+    pub height: f32,
+    pub ascent: f32,
+    pub descent: f32,
+}
+
+pub fn measure(face: &FontFace, text: &str, font_size: f32) -> TextMetrics {
+    let layouted_text = text_layout::layout_run(face, text, font_size);
+    let font_metrics = face.font.metrics();
+    let scale = font_size / (font_metrics.units_per_em as f32);
+    TextMetrics {
+        width: layouted_text.size.width,
+        height: layouted_text.size.height,
+        ascent: font_metrics.ascent * scale,
+        descent: -font_metrics.descent * scale,
+    }
+}
+
+thread_local! {
+    // faces are not Send, so every thread calling `measure_text` loads its own
+    static FACES: RefCell<FxHashMap<String, FontFace>> = RefCell::new(FxHashMap::default());
+}
+
+/// Measures `text` with the font file at `font_path`, or the default font if it's not given.
+pub fn measure_text(font_path: Option<&str>, text: &str, font_size: f32) -> io::Result<TextMetrics> {
+    let path = font_path.filter(|p| !p.is_empty());
+    // the default font is cached under the empty path
+    let key = path.unwrap_or("");
+    FACES.with(|faces| {
+        let mut faces = faces.borrow_mut();
+        if !faces.contains_key(key) {
+            let face = match path {
+                Some(path) => FontFace::from_file(path)?,
+                None => FontFace::from_bytes(DEFAULT_FONT.to_vec())?,
+            };
+            faces.insert(key.to_string(), face);
+        }
+        Ok(measure(&faces[key], text, font_size))
+    })
+}
+
 pub struct FontsManager {
     api: RenderApi,
     pub font: Font,
@@ -63,7 +129,7 @@ impl FontsManager {
     pub fn new(api: RenderApi, document_id: DocumentId) -> Self {
         let font_size = 14.0;
         let mut txn = Transaction::new();
-        let font = load_font(&api, &mut txn, "resources/Fira Code/ttf/FiraCode-Retina.ttf");
+        let font = load_font(&api, &mut txn, DEFAULT_FONT.to_vec());
         let font_instance_key = add_font_instance(&api, &mut txn, font.font_key, font_size as i32);
         api.send_transaction(document_id, txn);
         FontsManager {
@@ -77,6 +143,23 @@ impl FontsManager {
     pub fn layout_simple_ascii(
         &mut self,
         text: &str) -> LayoutedText {
-        return crate::text_layout::layout_run(&self.font, text, self.font_size);
+        return crate::text_layout::layout_run(&self.font.face, text, self.font_size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_measure_text() {
+        let one = measure_text(None, "a", 14.0).unwrap();
+        let three = measure_text(None, "abc", 14.0).unwrap();
+        assert!(one.width > 0.0);
+        // Fira Code is monospaced
+        assert!((three.width - 3.0 * one.width).abs() < 0.01);
+        assert!(one.ascent > 0.0 && one.descent > 0.0);
+        assert!((one.height - (one.ascent + one.descent)).abs() < 0.01);
+        assert!(measure_text(Some("no/such/font.ttf"), "a", 14.0).is_err());
     }
 }
@@ -98,7 +98,7 @@ impl Drop for HbFace {
     }
 }
 
-pub fn layout_run(font: &crate::text::Font, text: &str, font_size: f32) -> text::LayoutedText {
+pub fn layout_run(font: &crate::text::FontFace, text: &str, font_size: f32) -> text::LayoutedText {
     profile_scope!("Measure text");
     let mut b = Buffer::new();
     b.add_str(text);
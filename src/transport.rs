@@ -1,23 +1,28 @@
 
-use serde::{Deserialize};
+use serde::{Serialize, Deserialize};
 
 use serde_json::Value;
 
-#[derive(Debug, Deserialize)]
+use crate::error::PhotonError;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct MakeNode {
     #[serde(rename = "node")]
     pub node_id: u64,
     #[serde(rename = "type")]
     pub node_type: String,
+    /// Initial attribute values; the node is only created if all of them apply.
+    #[serde(rename = "attrs", default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub attributes: serde_json::Map<String, Value>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Destroy {
     #[serde(rename = "node")]
     pub node_id: u64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Add {
     #[serde(rename = "node")]
     pub node_id: u64,
@@ -27,7 +32,7 @@ pub struct Add {
     pub value: Value
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Remove {
     #[serde(rename = "node")]
     pub node_id: u64,
@@ -36,7 +41,27 @@ pub struct Remove {
     pub value: Value
 }
 
-#[derive(Debug, Deserialize)]
+/// Moves `value` within the list to `index`, counted in the list after the move.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Move {
+    #[serde(rename = "node")]
+    pub node_id: u64,
+    #[serde(rename = "attr")]
+    pub attribute: String,
+    pub index: usize,
+    pub value: Value
+}
+
+/// Replaces all children of a node at once.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SetChildren {
+    #[serde(rename = "node")]
+    pub node_id: u64,
+    #[serde(rename = "value")]
+    pub children: Vec<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct SetAttr {
     #[serde(rename = "node")]
     pub node_id: u64,
@@ -45,7 +70,7 @@ pub struct SetAttr {
     pub value: Value
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub enum Callback {
     #[serde(rename = "noria-handler-sync")]
     Sync,
@@ -55,7 +80,7 @@ pub enum Callback {
     None,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct SetCallback {
     #[serde(rename = "node")]
     pub node_id: u64,
@@ -65,7 +90,7 @@ pub struct SetCallback {
     pub callback: Callback,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "update-type")]
 pub enum Update {
     #[serde(rename = "make-node")]
@@ -76,21 +101,97 @@ pub enum Update {
     Add(Add),
     #[serde(rename = "remove")]
     Remove(Remove),
+    #[serde(rename = "move")]
+    Move(Move),
+    #[serde(rename = "set-children")]
+    SetChildren(SetChildren),
     #[serde(rename = "set-attr")]
     SetAttr(SetAttr),
     #[serde(rename = "set-callback")]
     SetCallback(SetCallback)
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum UpdateOrLogId {
     Update(Update),
     LogIds(Vec<u64>),
+    /// Anything else, kept so one bad update doesn't reject the whole batch.
+    Malformed(Value),
 }
 
 pub type NoriaUpdates = Vec<UpdateOrLogId>;
 
+/// Payload encoding of update batches and client messages, negotiated during handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Encoding {
+    #[serde(rename = "json")]
+    Json,
+    #[serde(rename = "msgpack")]
+    MessagePack,
+}
+
+impl Encoding {
+    pub const ALL: &'static [Encoding] = &[Encoding::Json, Encoding::MessagePack];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MessagePack => "msgpack",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        Encoding::ALL.iter().cloned().find(|e| e.name() == name)
+    }
+
+    pub fn decode_updates(self, bytes: &[u8]) -> Result<NoriaUpdates, PhotonError> {
+        let result = match self {
+            Encoding::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Encoding::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+        };
+        result.map_err(|e| PhotonError::Protocol(format!("can't decode {} update batch: {}", self.name(), e)))
+    }
+
+    pub fn encode<T: Serialize>(self, value: &T) -> Vec<u8> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value).expect("Can't serialize message"),
+            // named, so structs become maps and tagged enums stay decodable
+            Encoding::MessagePack => rmp_serde::to_vec_named(value).expect("Can't serialize message"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind")]
+pub enum ClientMessage {
+    #[serde(rename = "snapshot-request")]
+    SnapshotRequest,
+    #[serde(rename = "error")]
+    Error {
+        #[serde(rename = "log-ids")]
+        log_ids: Vec<u64>,
+        #[serde(rename = "update-index")]
+        update_index: Option<usize>,
+        #[serde(rename = "error-type")]
+        error_type: &'static str,
+        node: Option<u64>,
+        message: String,
+    },
+    /// Sent once the frame containing the batch's updates is rendered; timestamps are unix millis.
+    #[serde(rename = "frame-presented")]
+    FramePresented {
+        #[serde(rename = "log-ids")]
+        log_ids: Vec<u64>,
+        #[serde(rename = "received-ts")]
+        received_ts: u64,
+        #[serde(rename = "sent-ts")]
+        sent_ts: u64,
+        #[serde(rename = "presented-ts")]
+        presented_ts: u64,
+    },
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,19 +199,175 @@ mod tests {
     #[test]
     fn test_add() {
         let json = r#"
-        [42,
+        [[42],
          {
            "update-type": "make-node",
            "type": "foo",
            "node": 42
          },
+         {
+           "update-type": "add",
+           "node": 42,
+           "attr": "children",
+           "index": 0,
+           "value": 43
+         }]
+        "#;
+        let updates = serde_json::from_str::<NoriaUpdates>(json).unwrap();
+        assert_eq!(updates, vec![
+            UpdateOrLogId::LogIds(vec![42]),
+            UpdateOrLogId::Update(Update::MakeNode(MakeNode { node_id: 42, node_type: "foo".to_string(), attributes: serde_json::Map::new() })),
+            UpdateOrLogId::Update(Update::Add(Add { node_id: 42, attribute: "children".to_string(), index: 0, value: Value::from(43) })),
+        ]);
+    }
+
+    fn sample_batch(size: u64) -> NoriaUpdates {
+        let mut updates = vec![UpdateOrLogId::LogIds(vec![1, 2])];
+        for node_id in 0..size {
+            updates.push(UpdateOrLogId::Update(Update::MakeNode(MakeNode { node_id, node_type: "text".to_string(), attributes: serde_json::Map::new() })));
+            updates.push(UpdateOrLogId::Update(Update::SetAttr(SetAttr {
+                node_id,
+                attribute: "text".to_string(),
+                value: Value::from(format!("line number {}", node_id)),
+            })));
+            updates.push(UpdateOrLogId::Update(Update::SetAttr(SetAttr {
+                node_id,
+                attribute: "origin".to_string(),
+                value: serde_json::json!({"x": 0.5, "y": node_id * 16}),
+            })));
+            updates.push(UpdateOrLogId::Update(Update::Add(Add {
+                node_id: size,
+                attribute: "children".to_string(),
+                index: node_id as usize,
+                value: Value::from(node_id),
+            })));
+        }
+        updates.push(UpdateOrLogId::Update(Update::SetCallback(SetCallback {
+            node_id: 0,
+            attribute: "on-click".to_string(),
+            callback: Callback::Async,
+        })));
+        updates.push(UpdateOrLogId::Update(Update::Remove(Remove {
+            node_id: size,
+            attribute: "children".to_string(),
+            value: Value::from(0),
+        })));
+        updates.push(UpdateOrLogId::Update(Update::Destroy(Destroy { node_id: 0 })));
+        updates
+    }
+
+    #[test]
+    fn test_encodings_round_trip() {
+        let batch = sample_batch(10);
+        for encoding in Encoding::ALL {
+            let bytes = encoding.encode(&batch);
+            assert_eq!(encoding.decode_updates(&bytes).unwrap(), batch, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn test_encodings_reject_garbage() {
+        for encoding in Encoding::ALL {
+            match encoding.decode_updates(b"\xc1 not a batch") {
+                Err(PhotonError::Protocol(_)) => (),
+                other => panic!("{:?} {:?}", encoding, other),
+            }
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_encodings() {
+        let batch = sample_batch(20_000);
+        for encoding in Encoding::ALL {
+            let bytes = encoding.encode(&batch);
+            let start = std::time::Instant::now();
+            const RUNS: u32 = 10;
+            for _ in 0..RUNS {
+                encoding.decode_updates(&bytes).unwrap();
+            }
+            println!("{}: {} bytes, {:?} per decode", encoding.name(), bytes.len(), start.elapsed() / RUNS);
+        }
+    }
+
+    #[test]
+    fn test_make_node_attributes() {
+        let json = r#"
+        [{"update-type": "make-node", "type": "div", "node": 1},
+         {"update-type": "make-node", "type": "div", "node": 2, "attrs": {"rect": {"x": 0, "y": 0, "width": 10, "height": 5}}}]
+        "#;
+        let updates = serde_json::from_str::<NoriaUpdates>(json).unwrap();
+        match &updates[..] {
+            [UpdateOrLogId::Update(Update::MakeNode(plain)), UpdateOrLogId::Update(Update::MakeNode(with_attrs))] => {
+                assert!(plain.attributes.is_empty());
+                assert_eq!(with_attrs.attributes["rect"]["width"], 10);
+            }
+            other => panic!("{:?}", other),
+        }
+        for encoding in Encoding::ALL {
+            assert_eq!(encoding.decode_updates(&encoding.encode(&updates)).unwrap(), updates);
+        }
+    }
+
+    #[test]
+    fn test_reorder_updates() {
+        let json = r#"
+        [{"update-type": "move", "node": 1, "attr": "children", "index": 0, "value": 3},
+         {"update-type": "set-children", "node": 1, "value": [3, 2]}]
+        "#;
+        let updates = serde_json::from_str::<NoriaUpdates>(json).unwrap();
+        assert_eq!(updates, vec![
+            UpdateOrLogId::Update(Update::Move(Move { node_id: 1, attribute: "children".to_string(), index: 0, value: Value::from(3) })),
+            UpdateOrLogId::Update(Update::SetChildren(SetChildren { node_id: 1, children: vec![3, 2] })),
+        ]);
+    }
+
+    #[test]
+    fn test_frame_presented_message() {
+        let msg = ClientMessage::FramePresented {
+            log_ids: vec![3, 4],
+            received_ts: 10,
+            sent_ts: 12,
+            presented_ts: 20,
+        };
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "kind": "frame-presented",
+            "log-ids": [3, 4],
+            "received-ts": 10,
+            "sent-ts": 12,
+            "presented-ts": 20
+        }));
+    }
+
+    #[test]
+    fn test_malformed_update_keeps_batch() {
+        let json = r#"
+        [[1, 2],
+         {
+           "update-type": "make-node",
+           "type": "div",
+           "node": 42
+         },
          {
            "update-type": "make-node",
-           "type": "bar",
-           "node": 43
+           "node": "not an id"
          }]
         "#;
-        let updates = serde_json::from_str::<Update>(json).unwrap();
+        let updates = serde_json::from_str::<NoriaUpdates>(json).unwrap();
+        assert_eq!(updates.len(), 3);
+        match &updates[0] {
+            UpdateOrLogId::LogIds(ids) => assert_eq!(ids, &vec![1, 2]),
+            other => panic!("{:?}", other),
+        }
+        match &updates[1] {
+            UpdateOrLogId::Update(Update::MakeNode(make_node)) => assert_eq!(make_node.node_id, 42),
+            other => panic!("{:?}", other),
+        }
+        match &updates[2] {
+            UpdateOrLogId::Malformed(value) => assert_eq!(value["node"], "not an id"),
+            other => panic!("{:?}", other),
+        }
     }
 
 }
\ No newline at end of file